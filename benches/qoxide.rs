@@ -4,6 +4,7 @@ use std::hint::black_box;
 
 const LARGE_PAYLOAD_SIZE: usize = 1000000; // 1MB
 const QUEUE_SIZES: [usize; 3] = [1000, 10_000, 100_000];
+const BATCH_SIZE: usize = 100;
 
 fn bench_queue_add(c: &mut Criterion) {
     let mut group = c.benchmark_group("queue_add");
@@ -18,6 +19,15 @@ fn bench_queue_add(c: &mut Criterion) {
     group.bench_function("queue_add_large_payload", |b| {
         b.iter(|| queue.add(black_box(payload.clone())))
     });
+    group.finish();
+
+    let mut group = c.benchmark_group("queue_add_batch");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    let payloads = vec![b"0".to_vec(); BATCH_SIZE];
+    group.bench_function("queue_add_batch", |b| {
+        b.iter(|| queue.add_batch(black_box(payloads.clone())))
+    });
+    group.finish();
 }
 
 fn bench_queue_reserve(c: &mut Criterion) {
@@ -58,10 +68,10 @@ fn bench_queue_interactions(c: &mut Criterion) {
     group.bench_function("queue_interactions", |b| {
         b.iter(|| {
             let _ = queue.add(black_box(payload.clone()));
-            let (id, _) = queue.reserve().expect("Message should be found");
-            let _ = queue.fail(id);
-            let (id, _) = queue.reserve().expect("Message should be found");
-            let _ = queue.complete(id);
+            let reservation = queue.reserve().expect("Message should be found");
+            let _ = queue.fail(reservation.id, &reservation.token);
+            let reservation = queue.reserve().expect("Message should be found");
+            let _ = queue.complete(reservation.id, &reservation.token);
         })
     });
 }
@@ -0,0 +1,266 @@
+use crate::Error;
+use crate::{
+    AddOptions, FailOptions, Group, GroupSummary, ListFilter, Message, MessageState,
+    MessageSummary, QoxideQueue, QueueSize, Reservation, SharedQoxideQueue,
+};
+use std::time::{Duration, SystemTime};
+
+/// An async handle to a queue for use with tokio.
+///
+/// SQLite calls are blocking, so every operation runs on tokio's blocking thread
+/// pool via [`spawn_blocking`](tokio::task::spawn_blocking) instead of the async
+/// executor. Cloning the handle is cheap and every clone shares the same
+/// connection pool as a [`SharedQoxideQueue`].
+///
+/// Requires the `async` feature and a tokio runtime.
+///
+/// # Example
+///
+/// ```
+/// use qoxide::QoxideQueue;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), qoxide::Error> {
+/// let queue = QoxideQueue::builder().build_async()?;
+///
+/// queue.add(b"job".to_vec()).await?;
+/// let reservation = queue.reserve().await?;
+/// queue.complete(reservation.id, &reservation.token).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncQoxideQueue {
+    shared: SharedQoxideQueue,
+}
+
+impl AsyncQoxideQueue {
+    pub(crate) fn new(shared: SharedQoxideQueue) -> Self {
+        Self { shared }
+    }
+
+    /// Runs `f` on the blocking thread pool with exclusive access to one of the pooled queues.
+    ///
+    /// Panics inside `f` are propagated to the caller.
+    pub async fn with<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut QoxideQueue) -> Result<T, Error> + Send + 'static,
+    {
+        let shared = self.shared.clone();
+        match tokio::task::spawn_blocking(move || shared.with(f)).await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Returns the name of the queue within the database.
+    pub fn name(&self) -> &str {
+        self.shared.name()
+    }
+
+    /// See [`QoxideQueue::size`].
+    pub async fn size(&self) -> Result<QueueSize, Error> {
+        self.with(|queue| queue.size()).await
+    }
+
+    /// See [`QoxideQueue::get`].
+    pub async fn get(&self, id: i64) -> Result<Vec<u8>, Error> {
+        self.with(move |queue| queue.get(id)).await
+    }
+
+    /// See [`QoxideQueue::get_message`].
+    pub async fn get_message(&self, id: i64) -> Result<Message, Error> {
+        self.with(move |queue| queue.get_message(id)).await
+    }
+
+    /// See [`QoxideQueue::add`].
+    pub async fn add(&self, payload: Vec<u8>) -> Result<i64, Error> {
+        self.with(move |queue| queue.add(payload)).await
+    }
+
+    /// See [`QoxideQueue::add_with`].
+    pub async fn add_with(&self, payload: Vec<u8>, options: AddOptions) -> Result<i64, Error> {
+        self.with(move |queue| queue.add_with(payload, options))
+            .await
+    }
+
+    /// See [`QoxideQueue::add_delayed`].
+    pub async fn add_delayed(&self, payload: Vec<u8>, delay: Duration) -> Result<i64, Error> {
+        self.with(move |queue| queue.add_delayed(payload, delay))
+            .await
+    }
+
+    /// See [`QoxideQueue::add_at`].
+    pub async fn add_at(&self, payload: Vec<u8>, time: SystemTime) -> Result<i64, Error> {
+        self.with(move |queue| queue.add_at(payload, time)).await
+    }
+
+    /// See [`QoxideQueue::add_with_priority`].
+    pub async fn add_with_priority(&self, payload: Vec<u8>, priority: i64) -> Result<i64, Error> {
+        self.with(move |queue| queue.add_with_priority(payload, priority))
+            .await
+    }
+
+    /// See [`QoxideQueue::add_with_ttl`].
+    pub async fn add_with_ttl(&self, payload: Vec<u8>, ttl: Duration) -> Result<i64, Error> {
+        self.with(move |queue| queue.add_with_ttl(payload, ttl))
+            .await
+    }
+
+    /// See [`QoxideQueue::add_unique`].
+    pub async fn add_unique(&self, key: &str, payload: Vec<u8>) -> Result<i64, Error> {
+        let key = key.to_string();
+        self.with(move |queue| queue.add_unique(&key, payload))
+            .await
+    }
+
+    /// See [`QoxideQueue::add_after`].
+    pub async fn add_after(&self, payload: Vec<u8>, parents: &[i64]) -> Result<i64, Error> {
+        let parents = parents.to_vec();
+        self.with(move |queue| queue.add_after(payload, &parents))
+            .await
+    }
+
+    /// See [`QoxideQueue::add_group`].
+    pub async fn add_group(
+        &self,
+        payloads: Vec<Vec<u8>>,
+        callback_queue: &str,
+    ) -> Result<Group, Error> {
+        let callback_queue = callback_queue.to_string();
+        self.with(move |queue| queue.add_group(payloads, &callback_queue))
+            .await
+    }
+
+    /// See [`QoxideQueue::group`].
+    pub async fn group(&self, id: i64) -> Result<GroupSummary, Error> {
+        self.with(move |queue| queue.group(id)).await
+    }
+
+    /// See [`QoxideQueue::add_batch`].
+    pub async fn add_batch(&self, payloads: Vec<Vec<u8>>) -> Result<Vec<i64>, Error> {
+        self.with(move |queue| queue.add_batch(payloads)).await
+    }
+
+    /// See [`QoxideQueue::fan_out`].
+    pub async fn fan_out(&self, payload: Vec<u8>, n: usize) -> Result<Vec<i64>, Error> {
+        self.with(move |queue| queue.fan_out(payload, n)).await
+    }
+
+    /// See [`QoxideQueue::reserve`].
+    pub async fn reserve(&self) -> Result<Reservation, Error> {
+        self.with(|queue| queue.reserve()).await
+    }
+
+    /// See [`QoxideQueue::reserve_wait`].
+    ///
+    /// The wait occupies a thread from tokio's blocking pool, not the async executor.
+    pub async fn reserve_wait(&self, timeout: Duration) -> Result<Reservation, Error> {
+        let shared = self.shared.clone();
+        match tokio::task::spawn_blocking(move || shared.reserve_wait(timeout)).await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// See [`QoxideQueue::reserve_batch`].
+    pub async fn reserve_batch(&self, n: usize) -> Result<Vec<Reservation>, Error> {
+        self.with(move |queue| queue.reserve_batch(n)).await
+    }
+
+    /// See [`QoxideQueue::complete`].
+    pub async fn complete(&self, id: i64, token: &str) -> Result<(), Error> {
+        let token = token.to_string();
+        self.with(move |queue| queue.complete(id, &token)).await
+    }
+
+    /// See [`QoxideQueue::extend`].
+    pub async fn extend(&self, id: i64, token: &str, duration: Duration) -> Result<(), Error> {
+        let token = token.to_string();
+        self.with(move |queue| queue.extend(id, &token, duration))
+            .await
+    }
+
+    /// See [`QoxideQueue::heartbeat`].
+    pub async fn heartbeat(&self, id: i64, token: &str) -> Result<(), Error> {
+        let token = token.to_string();
+        self.with(move |queue| queue.heartbeat(id, &token)).await
+    }
+
+    /// See [`QoxideQueue::fail`].
+    pub async fn fail(&self, id: i64, token: &str) -> Result<MessageState, Error> {
+        let token = token.to_string();
+        self.with(move |queue| queue.fail(id, &token)).await
+    }
+
+    /// See [`QoxideQueue::fail_with_reason`].
+    pub async fn fail_with_reason(
+        &self,
+        id: i64,
+        token: &str,
+        reason: &str,
+    ) -> Result<MessageState, Error> {
+        let token = token.to_string();
+        let reason = reason.to_string();
+        self.with(move |queue| queue.fail_with_reason(id, &token, &reason))
+            .await
+    }
+
+    /// See [`QoxideQueue::fail_with`].
+    pub async fn fail_with(
+        &self,
+        id: i64,
+        token: &str,
+        options: FailOptions,
+    ) -> Result<MessageState, Error> {
+        let token = token.to_string();
+        self.with(move |queue| queue.fail_with(id, &token, options))
+            .await
+    }
+
+    /// See [`QoxideQueue::fail_with_delay`].
+    pub async fn fail_with_delay(
+        &self,
+        id: i64,
+        token: &str,
+        delay: Duration,
+    ) -> Result<MessageState, Error> {
+        let token = token.to_string();
+        self.with(move |queue| queue.fail_with_delay(id, &token, delay))
+            .await
+    }
+
+    /// See [`QoxideQueue::remove`].
+    pub async fn remove(&self, id: i64) -> Result<(), Error> {
+        self.with(move |queue| queue.remove(id)).await
+    }
+
+    /// See [`QoxideQueue::purge`].
+    pub async fn purge(&self, state: MessageState, older_than: Duration) -> Result<usize, Error> {
+        self.with(move |queue| queue.purge(state, older_than)).await
+    }
+
+    /// See [`QoxideQueue::compact`].
+    pub async fn compact(&self) -> Result<u64, Error> {
+        self.with(|queue| queue.compact()).await
+    }
+
+    /// See [`QoxideQueue::list`].
+    pub async fn list(&self, filter: &ListFilter) -> Result<Vec<MessageSummary>, Error> {
+        let filter = filter.clone();
+        self.with(move |queue| queue.list(&filter)).await
+    }
+
+    /// See [`QoxideQueue::dead_letters`].
+    pub async fn dead_letters(&self) -> Result<Vec<i64>, Error> {
+        self.with(|queue| queue.dead_letters()).await
+    }
+
+    /// See [`QoxideQueue::requeue_dead_letters`].
+    pub async fn requeue_dead_letters(&self, ids: &[i64]) -> Result<(), Error> {
+        let ids = ids.to_vec();
+        self.with(move |queue| queue.requeue_dead_letters(&ids))
+            .await
+    }
+}
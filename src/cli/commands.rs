@@ -1,26 +1,70 @@
 use crate::cli::output;
 use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
-use qoxide::{MessageState, QoxideQueue};
+use qoxide::{AddOptions, Error, FailOptions, ListFilter, MessageState, QoxideQueue};
 use serde::Serialize;
 use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn open_queue(db_path: &str) -> QoxideQueue {
+pub fn open_queue(db_path: &str, queue_name: &str) -> QoxideQueue {
     QoxideQueue::builder()
         .path(db_path)
+        .name(queue_name)
         .build()
         .unwrap_or_else(|err| {
             eprintln!("Failed to open queue: {}", err);
-            process::exit(1);
+            process::exit(exit_code(&err));
         })
 }
 
+/// Exit code for storage failures and other unexpected errors.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code when there is no pending message to reserve.
+pub const EXIT_EMPTY: i32 = 3;
+/// Exit code when the message ID does not exist in the queue.
+pub const EXIT_NOT_FOUND: i32 = 4;
+/// Exit code when the message's state does not allow the operation.
+pub const EXIT_INVALID_TRANSITION: i32 = 5;
+/// Exit code when the queue configuration is invalid.
+pub const EXIT_CONFIG: i32 = 6;
+/// Exit code when the reservation token no longer owns the message.
+pub const EXIT_STALE_RESERVATION: i32 = 7;
+
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Empty => EXIT_EMPTY,
+        Error::NotFound(_) | Error::GroupNotFound(_) => EXIT_NOT_FOUND,
+        Error::StaleReservation(_) => EXIT_STALE_RESERVATION,
+        Error::InvalidTransition { .. } => EXIT_INVALID_TRANSITION,
+        Error::Config(_) => EXIT_CONFIG,
+        Error::Storage(_) => EXIT_FAILURE,
+    }
+}
+
+/// Reports a failed queue operation and exits with a code identifying the error.
+pub fn exit_with_error(context: &str, err: &Error, json: bool) -> ! {
+    if json {
+        output::print_json_error(&format!("{}: {}", context, err));
+    } else {
+        eprintln!("{}", err);
+    }
+    process::exit(exit_code(err));
+}
+
 #[derive(Serialize)]
 pub struct AddResult {
     pub id: i64,
 }
 
-pub fn add(db_path: &str, payload: &str, utf8: bool, json: bool) {
-    let mut queue = open_queue(db_path);
+pub fn add(
+    db_path: &str,
+    queue_name: &str,
+    payload: &str,
+    utf8: bool,
+    delay: Option<u64>,
+    key: Option<&str>,
+    json: bool,
+) {
+    let mut queue = open_queue(db_path, queue_name);
 
     let bytes = if utf8 {
         payload.as_bytes().to_vec()
@@ -35,7 +79,15 @@ pub fn add(db_path: &str, payload: &str, utf8: bool, json: bool) {
         })
     };
 
-    match queue.add(bytes) {
+    let mut options = AddOptions::new();
+    if let Some(seconds) = delay {
+        options = options.delay(Duration::from_secs(seconds));
+    }
+    if let Some(key) = key {
+        options = options.key(key);
+    }
+
+    match queue.add_with(bytes, options) {
         Ok(id) => {
             if json {
                 output::print_json(AddResult { id });
@@ -43,14 +95,7 @@ pub fn add(db_path: &str, payload: &str, utf8: bool, json: bool) {
                 println!("{}", id);
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to add message: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to add message", &err, json),
     }
 }
 
@@ -58,15 +103,21 @@ pub fn add(db_path: &str, payload: &str, utf8: bool, json: bool) {
 pub struct ReserveResult {
     pub id: i64,
     pub payload: String,
+    pub token: String,
 }
 
-pub fn reserve(db_path: &str, utf8: bool, json: bool) {
-    let mut queue = open_queue(db_path);
+pub fn reserve(db_path: &str, queue_name: &str, utf8: bool, wait: Option<u64>, json: bool) {
+    let mut queue = open_queue(db_path, queue_name);
+
+    let result = match wait {
+        Some(seconds) => queue.reserve_wait(Duration::from_secs(seconds)),
+        None => queue.reserve(),
+    };
 
-    match queue.reserve() {
-        Ok((id, payload)) => {
+    match result {
+        Ok(reservation) => {
             let payload_str = if utf8 {
-                String::from_utf8(payload).unwrap_or_else(|_| {
+                String::from_utf8(reservation.payload).unwrap_or_else(|_| {
                     if json {
                         output::print_json_error("Payload is not valid UTF-8");
                     } else {
@@ -75,47 +126,35 @@ pub fn reserve(db_path: &str, utf8: bool, json: bool) {
                     process::exit(1);
                 })
             } else {
-                BASE64.encode(&payload)
+                BASE64.encode(&reservation.payload)
             };
 
             if json {
                 output::print_json(ReserveResult {
-                    id,
+                    id: reservation.id,
                     payload: payload_str,
+                    token: reservation.token,
                 });
             } else {
-                println!("{}", id);
+                println!("{}", reservation.id);
                 println!("{}", payload_str);
+                println!("{}", reservation.token);
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to reserve message: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to reserve message", &err, json),
     }
 }
 
-pub fn complete(db_path: &str, id: i64, json: bool) {
-    let queue = open_queue(db_path);
+pub fn complete(db_path: &str, queue_name: &str, id: i64, token: &str, json: bool) {
+    let mut queue = open_queue(db_path, queue_name);
 
-    match queue.complete(id) {
+    match queue.complete(id, token) {
         Ok(()) => {
             if json {
                 output::print_json(serde_json::json!({"id": id, "status": "completed"}));
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to complete message: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to complete message", &err, json),
     }
 }
 
@@ -125,10 +164,26 @@ pub struct FailResult {
     pub new_state: String,
 }
 
-pub fn fail(db_path: &str, id: i64, json: bool) {
-    let mut queue = open_queue(db_path);
+pub fn fail(
+    db_path: &str,
+    queue_name: &str,
+    id: i64,
+    token: &str,
+    delay: Option<u64>,
+    reason: Option<&str>,
+    json: bool,
+) {
+    let mut queue = open_queue(db_path, queue_name);
+
+    let mut options = FailOptions::new();
+    if let Some(seconds) = delay {
+        options = options.delay(Duration::from_secs(seconds));
+    }
+    if let Some(reason) = reason {
+        options = options.reason(reason);
+    }
 
-    match queue.fail(id) {
+    match queue.fail_with(id, token, options) {
         Ok(new_state) => {
             let state_str = match new_state {
                 MessageState::Pending => "PENDING",
@@ -143,19 +198,12 @@ pub fn fail(db_path: &str, id: i64, json: bool) {
                 });
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to fail message: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to fail message", &err, json),
     }
 }
 
-pub fn remove(db_path: &str, id: i64, json: bool) {
-    let mut queue = open_queue(db_path);
+pub fn remove(db_path: &str, queue_name: &str, id: i64, json: bool) {
+    let mut queue = open_queue(db_path, queue_name);
 
     match queue.remove(id) {
         Ok(()) => {
@@ -163,14 +211,7 @@ pub fn remove(db_path: &str, id: i64, json: bool) {
                 output::print_json(serde_json::json!({"id": id, "status": "removed"}));
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to remove message: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to remove message", &err, json),
     }
 }
 
@@ -180,8 +221,8 @@ pub struct GetResult {
     pub payload: String,
 }
 
-pub fn get(db_path: &str, id: i64, utf8: bool, json: bool) {
-    let queue = open_queue(db_path);
+pub fn get(db_path: &str, queue_name: &str, id: i64, utf8: bool, json: bool) {
+    let queue = open_queue(db_path, queue_name);
 
     match queue.get(id) {
         Ok(payload) => {
@@ -207,14 +248,7 @@ pub fn get(db_path: &str, id: i64, utf8: bool, json: bool) {
                 println!("{}", payload_str);
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to get message: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to get message", &err, json),
     }
 }
 
@@ -225,10 +259,12 @@ pub struct SizeResult {
     pub reserved: usize,
     pub completed: usize,
     pub dead: usize,
+    pub expired: usize,
+    pub cancelled: usize,
 }
 
-pub fn show_size(db_path: &str, json: bool) {
-    let queue = open_queue(db_path);
+pub fn show_size(db_path: &str, queue_name: &str, json: bool) {
+    let queue = open_queue(db_path, queue_name);
 
     match queue.size() {
         Ok(size) => {
@@ -239,6 +275,8 @@ pub fn show_size(db_path: &str, json: bool) {
                     reserved: size.reserved,
                     completed: size.completed,
                     dead: size.dead,
+                    expired: size.expired,
+                    cancelled: size.cancelled,
                 });
             } else {
                 println!("total {}", size.total);
@@ -246,16 +284,11 @@ pub fn show_size(db_path: &str, json: bool) {
                 println!("reserved {}", size.reserved);
                 println!("completed {}", size.completed);
                 println!("dead {}", size.dead);
+                println!("expired {}", size.expired);
+                println!("cancelled {}", size.cancelled);
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to get queue size: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to get queue size", &err, json),
     }
 }
 
@@ -265,8 +298,8 @@ pub struct DeadLettersResult {
     pub count: usize,
 }
 
-pub fn list_dead_letters(db_path: &str, json: bool) {
-    let queue = open_queue(db_path);
+pub fn list_dead_letters(db_path: &str, queue_name: &str, json: bool) {
+    let queue = open_queue(db_path, queue_name);
 
     match queue.dead_letters() {
         Ok(ids) => {
@@ -281,14 +314,7 @@ pub fn list_dead_letters(db_path: &str, json: bool) {
                 }
             }
         }
-        Err(err) => {
-            if json {
-                output::print_json_error(&format!("Failed to list dead letters: {}", err));
-            } else {
-                eprintln!("{}", err);
-            }
-            process::exit(1);
-        }
+        Err(err) => exit_with_error("Failed to list dead letters", &err, json),
     }
 }
 
@@ -298,8 +324,8 @@ pub struct RequeueResult {
     pub count: usize,
 }
 
-pub fn requeue_dead_letters(db_path: &str, ids: &[i64], json: bool) {
-    let mut queue = open_queue(db_path);
+pub fn requeue_dead_letters(db_path: &str, queue_name: &str, ids: &[i64], json: bool) {
+    let mut queue = open_queue(db_path, queue_name);
 
     match queue.requeue_dead_letters(ids) {
         Ok(()) => {
@@ -310,13 +336,240 @@ pub fn requeue_dead_letters(db_path: &str, ids: &[i64], json: bool) {
                 });
             }
         }
-        Err(err) => {
+        Err(err) => exit_with_error("Failed to requeue messages", &err, json),
+    }
+}
+
+#[derive(Serialize)]
+pub struct InspectResult {
+    pub id: i64,
+    pub queue: String,
+    pub state: String,
+    pub payload: String,
+    pub attempt_count: u32,
+    pub priority: i64,
+    pub created_at: u128,
+    pub available_at: u128,
+    pub reserved_at: Option<u128>,
+    pub reserved_until: Option<u128>,
+    pub completed_at: Option<u128>,
+    pub expires_at: Option<u128>,
+}
+
+/// Milliseconds since the Unix epoch, matching how the queue stores timestamps.
+fn epoch_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0)
+}
+
+pub fn inspect(db_path: &str, queue_name: &str, id: i64, utf8: bool, json: bool) {
+    let queue = open_queue(db_path, queue_name);
+
+    match queue.get_message(id) {
+        Ok(message) => {
+            let payload_str = if utf8 {
+                String::from_utf8(message.payload).unwrap_or_else(|_| {
+                    if json {
+                        output::print_json_error("Payload is not valid UTF-8");
+                    } else {
+                        eprintln!("Error: Payload is not valid UTF-8");
+                    }
+                    process::exit(1);
+                })
+            } else {
+                BASE64.encode(&message.payload)
+            };
+            let result = InspectResult {
+                id: message.id,
+                queue: message.queue,
+                state: message.state.as_str().to_string(),
+                payload: payload_str,
+                attempt_count: message.attempt_count,
+                priority: message.priority,
+                created_at: epoch_millis(message.created_at),
+                available_at: epoch_millis(message.available_at),
+                reserved_at: message.reserved_at.map(epoch_millis),
+                reserved_until: message.reserved_until.map(epoch_millis),
+                completed_at: message.completed_at.map(epoch_millis),
+                expires_at: message.expires_at.map(epoch_millis),
+            };
+
+            if json {
+                output::print_json(result);
+            } else {
+                let optional = |time: Option<u128>| {
+                    time.map_or_else(|| "-".to_string(), |time| time.to_string())
+                };
+                println!("id {}", result.id);
+                println!("queue {}", result.queue);
+                println!("state {}", result.state);
+                println!("attempt_count {}", result.attempt_count);
+                println!("priority {}", result.priority);
+                println!("created_at {}", result.created_at);
+                println!("available_at {}", result.available_at);
+                println!("reserved_at {}", optional(result.reserved_at));
+                println!("reserved_until {}", optional(result.reserved_until));
+                println!("completed_at {}", optional(result.completed_at));
+                println!("expires_at {}", optional(result.expires_at));
+                println!("payload {}", result.payload);
+            }
+        }
+        Err(err) => exit_with_error("Failed to inspect message", &err, json),
+    }
+}
+
+#[derive(Serialize)]
+pub struct ListedMessage {
+    pub id: i64,
+    pub state: String,
+    pub attempt_count: u32,
+    pub priority: i64,
+    pub created_at: u128,
+}
+
+#[derive(Serialize)]
+pub struct ListResult {
+    pub messages: Vec<ListedMessage>,
+    pub count: usize,
+    pub next_cursor: Option<i64>,
+}
+
+pub fn list(
+    db_path: &str,
+    queue_name: &str,
+    state: Option<MessageState>,
+    limit: usize,
+    after: Option<i64>,
+    json: bool,
+) {
+    let queue = open_queue(db_path, queue_name);
+
+    let mut filter = ListFilter::new().limit(limit);
+    if let Some(state) = state {
+        filter = filter.state(state);
+    }
+    if let Some(cursor) = after {
+        filter = filter.after(cursor);
+    }
+
+    match queue.list(&filter) {
+        Ok(messages) => {
+            // A full page may have more messages after it
+            let next_cursor = messages
+                .last()
+                .filter(|_| messages.len() == limit)
+                .map(|message| message.id);
+            let messages: Vec<ListedMessage> = messages
+                .into_iter()
+                .map(|message| ListedMessage {
+                    id: message.id,
+                    state: message.state.as_str().to_string(),
+                    attempt_count: message.attempt_count,
+                    priority: message.priority,
+                    created_at: epoch_millis(message.created_at),
+                })
+                .collect();
+
+            if json {
+                output::print_json(ListResult {
+                    count: messages.len(),
+                    messages,
+                    next_cursor,
+                });
+            } else {
+                for message in messages {
+                    println!("{} {} {}", message.id, message.state, message.attempt_count);
+                }
+            }
+        }
+        Err(err) => exit_with_error("Failed to list messages", &err, json),
+    }
+}
+
+#[derive(Serialize)]
+pub struct PurgeResult {
+    pub purged: usize,
+}
+
+pub fn purge(db_path: &str, queue_name: &str, state: MessageState, older_than: u64, json: bool) {
+    let mut queue = open_queue(db_path, queue_name);
+
+    match queue.purge(state, Duration::from_secs(older_than)) {
+        Ok(purged) => {
             if json {
-                output::print_json_error(&format!("Failed to requeue messages: {}", err));
+                output::print_json(PurgeResult { purged });
             } else {
-                eprintln!("{}", err);
+                println!("{}", purged);
+            }
+        }
+        Err(err) => exit_with_error("Failed to purge messages", &err, json),
+    }
+}
+
+#[derive(Serialize)]
+pub struct CompactResult {
+    pub bytes_freed: u64,
+}
+
+pub fn compact(db_path: &str, queue_name: &str, json: bool) {
+    let mut queue = open_queue(db_path, queue_name);
+
+    match queue.compact() {
+        Ok(bytes_freed) => {
+            if json {
+                output::print_json(CompactResult { bytes_freed });
+            } else {
+                println!("{}", bytes_freed);
+            }
+        }
+        Err(err) => exit_with_error("Failed to compact database", &err, json),
+    }
+}
+
+#[derive(Serialize)]
+pub struct AttemptResult {
+    pub attempt: u32,
+    pub started_at: u128,
+    pub ended_at: Option<u128>,
+    pub outcome: Option<String>,
+    pub error: Option<String>,
+}
+
+pub fn history(db_path: &str, queue_name: &str, id: i64, json: bool) {
+    let queue = open_queue(db_path, queue_name);
+
+    match queue.get_message(id) {
+        Ok(message) => {
+            let attempts: Vec<AttemptResult> = message
+                .history
+                .into_iter()
+                .map(|attempt| AttemptResult {
+                    attempt: attempt.number,
+                    started_at: epoch_millis(attempt.started_at),
+                    ended_at: attempt.ended_at.map(epoch_millis),
+                    outcome: attempt.outcome.map(|outcome| outcome.as_str().to_string()),
+                    error: attempt.error,
+                })
+                .collect();
+
+            if json {
+                output::print_json(attempts);
+            } else {
+                for attempt in attempts {
+                    println!(
+                        "{} {} {} {} {}",
+                        attempt.attempt,
+                        attempt.started_at,
+                        attempt
+                            .ended_at
+                            .map_or_else(|| "-".to_string(), |time| time.to_string()),
+                        attempt.outcome.as_deref().unwrap_or("IN_PROGRESS"),
+                        attempt.error.as_deref().unwrap_or("")
+                    );
+                }
             }
-            process::exit(1);
         }
+        Err(err) => exit_with_error("Failed to get message history", &err, json),
     }
 }
@@ -0,0 +1,63 @@
+use crate::MessageState;
+use std::fmt;
+
+/// Errors returned by queue operations.
+#[derive(Debug)]
+pub enum Error {
+    /// No pending message is available to reserve.
+    Empty,
+    /// No message with the given ID exists in the queue.
+    NotFound(i64),
+    /// No group with the given ID exists in the queue.
+    GroupNotFound(i64),
+    /// The reservation token no longer owns the message, because the reservation
+    /// was reclaimed or the message was reserved again by another worker.
+    StaleReservation(i64),
+    /// The message's current state does not allow the requested transition.
+    InvalidTransition {
+        /// The state the message is in.
+        from: MessageState,
+        /// The state the operation would have moved it to.
+        to: MessageState,
+    },
+    /// The queue was built with an invalid configuration, or an operation was given an invalid setting.
+    Config(String),
+    /// The underlying SQLite database returned an error.
+    Storage(rusqlite::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Empty => write!(f, "no pending messages"),
+            Error::NotFound(id) => write!(f, "message {} not found", id),
+            Error::GroupNotFound(id) => write!(f, "group {} not found", id),
+            Error::StaleReservation(id) => {
+                write!(f, "reservation of message {} is no longer held", id)
+            }
+            Error::InvalidTransition { from, to } => write!(
+                f,
+                "cannot move message from {} to {}",
+                from.as_str(),
+                to.as_str()
+            ),
+            Error::Config(reason) => write!(f, "invalid configuration: {}", reason),
+            Error::Storage(err) => write!(f, "storage error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Storage(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Storage(err)
+    }
+}
@@ -1,15 +1,130 @@
 CREATE TABLE IF NOT EXISTS messages (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Name of the logical queue the message belongs to
+    queue TEXT NOT NULL DEFAULT 'default',
     state TEXT NOT NULL,
     payload_id INTEGER NOT NULL,
     attempt_count INTEGER NOT NULL DEFAULT 0,
+    -- Reservation deadline in milliseconds since the Unix epoch, NULL if not reserved or no timeout
+    reserved_until INTEGER,
+    -- Earliest reservation time in milliseconds since the Unix epoch, 0 if available immediately
+    available_at INTEGER NOT NULL DEFAULT 0,
+    -- Higher priorities are reserved first
+    priority INTEGER NOT NULL DEFAULT 0,
+    -- Expiry time in milliseconds since the Unix epoch if still pending, NULL if the message never expires
+    expires_at INTEGER,
+    -- Key used to suppress duplicates, NULL if the message is not deduplicated
+    dedup_key TEXT,
+    -- Completion time in milliseconds since the Unix epoch, NULL if not completed
+    completed_at INTEGER,
+    -- Token issued by the latest reservation, required to complete or fail the message
+    reservation_token TEXT,
+    -- Creation time in milliseconds since the Unix epoch, 0 for messages created before it was recorded
+    created_at INTEGER NOT NULL DEFAULT 0,
+    -- Start of the latest reservation in milliseconds since the Unix epoch, NULL if never reserved
+    reserved_at INTEGER,
+    -- Group the message was added to by add_group, NULL if not in a group
+    group_id INTEGER,
     FOREIGN KEY (payload_id) REFERENCES payloads (id)
 );
 
 CREATE TABLE IF NOT EXISTS payloads (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
-    data BLOB NOT NULL
+    data BLOB NOT NULL,
+    -- Content hash shared by identical payloads, NULL for payloads stored by older versions
+    hash TEXT
 );
 
+-- One row per reservation of a message
+CREATE TABLE IF NOT EXISTS attempts (
+    message_id INTEGER NOT NULL,
+    -- Starts at 1 for the first reservation of the message
+    attempt INTEGER NOT NULL,
+    -- Reservation time in milliseconds since the Unix epoch
+    started_at INTEGER NOT NULL,
+    -- End time in milliseconds since the Unix epoch, NULL while in progress
+    ended_at INTEGER,
+    -- COMPLETED, FAILED or TIMED_OUT, NULL while in progress
+    outcome TEXT,
+    -- Reason given when the attempt failed
+    error TEXT,
+    PRIMARY KEY (message_id, attempt)
+);
+
+-- Parents a message waits on, added by add_after
+CREATE TABLE IF NOT EXISTS dependencies (
+    message_id INTEGER NOT NULL,
+    -- Message that must be completed before message_id can be reserved
+    parent_id INTEGER NOT NULL,
+    PRIMARY KEY (message_id, parent_id)
+);
+
+-- Sets of messages added by add_group
+CREATE TABLE IF NOT EXISTS groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue TEXT NOT NULL,
+    -- Queue the callback message is added to when every member has finished
+    callback_queue TEXT NOT NULL,
+    -- Number of messages added to the group
+    total INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    -- Time the last member finished in milliseconds since the Unix epoch, NULL while any are pending or reserved
+    finished_at INTEGER,
+    -- ID of the callback message, NULL until the group finishes
+    callback_id INTEGER
+);
+
+-- Index for finding groups still waiting on members
+CREATE INDEX IF NOT EXISTS idx_groups_unfinished ON groups(queue) WHERE finished_at IS NULL;
+
+-- Index for finding the members of a group
+CREATE INDEX IF NOT EXISTS idx_messages_group_id ON messages(group_id) WHERE group_id IS NOT NULL;
+
+-- Index for finding the children of a message
+CREATE INDEX IF NOT EXISTS idx_dependencies_parent_id ON dependencies(parent_id);
+
 -- Index on state for efficient filtering and grouping
 CREATE INDEX IF NOT EXISTS idx_messages_state ON messages(state);
+
+-- Index matching the reserve order within a queue: highest priority first, FIFO within a priority.
+-- Covers available_at so delayed messages are skipped without reading the table.
+CREATE INDEX IF NOT EXISTS idx_messages_reserve ON messages(queue, state, priority DESC, id, available_at);
+
+-- Index for finding pending messages past their time-to-live
+CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages(queue, state, expires_at);
+
+-- Index for paging through a queue in ID order
+CREATE INDEX IF NOT EXISTS idx_messages_list ON messages(queue, id);
+
+-- Index for enforcing retention on completed messages
+CREATE INDEX IF NOT EXISTS idx_messages_completed_at ON messages(queue, state, completed_at);
+
+-- Index for finding an identical stored payload before inserting a new one
+CREATE UNIQUE INDEX IF NOT EXISTS idx_payloads_hash ON payloads(hash) WHERE hash IS NOT NULL;
+
+-- Index for finding the messages that use a payload
+CREATE INDEX IF NOT EXISTS idx_messages_payload_id ON messages(payload_id);
+
+-- Deletes a payload along with the last message using it
+CREATE TRIGGER IF NOT EXISTS messages_delete_payload AFTER DELETE ON messages
+WHEN NOT EXISTS (SELECT 1 FROM messages WHERE payload_id = OLD.payload_id)
+BEGIN
+    DELETE FROM payloads WHERE id = OLD.payload_id;
+END;
+
+-- Deletes a message's attempt history along with it
+CREATE TRIGGER IF NOT EXISTS messages_delete_attempts AFTER DELETE ON messages
+BEGIN
+    DELETE FROM attempts WHERE message_id = OLD.id;
+END;
+
+-- Deletes a message's dependencies along with it. Children of a completed parent are
+-- unblocked too; other deleted parents keep blocking them unless removed with remove()
+CREATE TRIGGER IF NOT EXISTS messages_delete_dependencies AFTER DELETE ON messages
+BEGIN
+    DELETE FROM dependencies
+    WHERE message_id = OLD.id OR (parent_id = OLD.id AND OLD.state = 'COMPLETED');
+END;
+
+-- Index for finding in-flight duplicates by key
+CREATE INDEX IF NOT EXISTS idx_messages_dedup_key ON messages(queue, dedup_key) WHERE dedup_key IS NOT NULL;
@@ -5,21 +5,22 @@
 //! ```
 //! use qoxide::QoxideQueue;
 //!
-//! # fn main() -> Result<(), rusqlite::Error> {
+//! # fn main() -> Result<(), qoxide::Error> {
 //! let mut queue = QoxideQueue::builder()
 //!     .path(":memory:")  // optional: persists to file
+//!     .name("emails")    // optional: shares the file with other named queues
 //!     .max_attempts(3)   // optional: moves to DLQ after 3 failed attempts
 //!     .build()?;
 //!
 //! // Add and process a message
 //! let id = queue.add(b"job payload".to_vec())?;
-//! let (id, payload) = queue.reserve()?;
-//! queue.complete(id)?;
+//! let reservation = queue.reserve()?;
+//! queue.complete(reservation.id, &reservation.token)?;
 //!
 //! // Or fail and retry
 //! let id = queue.add(b"another job".to_vec())?;
-//! let (id, _) = queue.reserve()?;
-//! queue.fail(id)?;
+//! let reservation = queue.reserve()?;
+//! queue.fail(reservation.id, &reservation.token)?;
 //!
 //! // Inspect and manage dead letters
 //! let dead_ids = queue.dead_letters()?;
@@ -28,20 +29,128 @@
 //! # }
 //! ```
 
-use rusqlite::{Connection, Error, params};
+use notify::Notifier;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, Value, ValueRef};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params, params_from_iter};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+#[cfg(feature = "async")]
+mod async_queue;
+mod error;
+mod notify;
+mod shared;
+mod worker;
+
+#[cfg(feature = "async")]
+pub use async_queue::AsyncQoxideQueue;
+pub use error::Error;
+pub use shared::SharedQoxideQueue;
+pub use worker::{Worker, WorkerHandle};
 
 /// A SQLite-backed message queue.
 ///
 /// Messages flow through states: `Pending` → `Reserved` → `Completed` (or `Dead`).
+/// Pending messages that outlive their time-to-live become `Expired`.
+/// Messages added with [`add_after`](Self::add_after) wait for their parents to complete,
+/// and become `Cancelled` if configured to when a parent fails.
+/// Pending messages are reserved highest priority first, oldest first within a priority.
 ///
 /// Use [`add`](Self::add) to enqueue, [`reserve`](Self::reserve) to dequeue,
 /// and [`complete`](Self::complete) or [`fail`](Self::fail) to finish processing.
+/// Each reservation carries a token that must be presented to finish it, so a
+/// worker cannot finish a message it no longer owns.
 ///
 /// Optionally configure a max attempts limit to move failed messages
-/// to the dead letter queue after N attempts.
+/// to the dead letter queue after N attempts, and a visibility timeout
+/// to reclaim reservations abandoned by crashed workers.
+///
+/// A single database file can host many named queues. Every operation is
+/// scoped to the queue's name, so messages are never shared between queues.
+///
+/// Operations that change state take `&mut self`. To share a queue between
+/// threads, use [`SharedQoxideQueue`] instead.
 pub struct QoxideQueue {
     db: Connection,
+    name: String,
     max_attempts: Option<u32>,
+    visibility_timeout: Option<Duration>,
+    ttl: Option<Duration>,
+    drop_expired: bool,
+    deduplicate_payloads: bool,
+    dedup_window: Option<Duration>,
+    retention: Option<RetentionPolicy>,
+    retry_policy: Option<RetryPolicy>,
+    dead_parent_policy: DeadParentPolicy,
+    notifier: Arc<Notifier>,
+}
+
+/// What happens to messages added with [`QoxideQueue::add_after`] when a parent
+/// can no longer complete, because it is dead, expired, or cancelled.
+///
+/// Set with [`QoxideQueueBuilder::dead_parent_policy`].
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum DeadParentPolicy {
+    /// Children stay pending and blocked, and run if the parent is requeued and completes.
+    #[default]
+    LeaveBlocked,
+    /// Pending children move to `Cancelled`, and so do their own children.
+    CancelChildren,
+}
+
+/// How long completed messages are kept before being deleted automatically.
+///
+/// Set with [`QoxideQueueBuilder::retention`]. The policy is enforced whenever a
+/// message is reserved or completed. Use [`QoxideQueue::purge`] to delete messages
+/// in other states.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RetentionPolicy {
+    /// Deletes completed messages once they have been completed for longer than the duration.
+    MaxAge(Duration),
+    /// Keeps only the given number of most recently completed messages.
+    KeepLast(usize),
+}
+
+/// How long a failed message waits before it can be reserved again.
+///
+/// Set with [`QoxideQueueBuilder::retry_policy`]. Delays are counted from the
+/// failure and grow with the message's attempt count, starting at 1 for the first failure.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RetryPolicy {
+    /// Waits the same duration after every failure.
+    Fixed(Duration),
+    /// Waits the duration multiplied by the attempt count.
+    Linear(Duration),
+    /// Doubles the wait after every failure, starting at `base` and capped at `max`.
+    ///
+    /// Each delay is randomized between half and all of the computed wait, so
+    /// messages that failed together do not all retry at the same moment.
+    Exponential {
+        /// The wait after the first failure, before jitter.
+        base: Duration,
+        /// The longest wait, before jitter.
+        max: Duration,
+    },
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retrying a message that has failed `attempt` times.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            RetryPolicy::Fixed(delay) => delay,
+            RetryPolicy::Linear(step) => step.saturating_mul(attempt),
+            RetryPolicy::Exponential { base, max } => {
+                let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+                let wait = base.saturating_mul(factor).min(max);
+                // Equal jitter: half the wait is fixed, the other half random.
+                // The top 48 bits of a v4 UUID are random, unlike its version and variant bits.
+                let random = (Uuid::new_v4().as_u128() >> 80) as f64 / (1u64 << 48) as f64;
+                wait / 2 + (wait / 2).mul_f64(random)
+            }
+        }
+    }
 }
 
 /// The state of a message in the queue.
@@ -55,6 +164,26 @@ pub enum MessageState {
     Completed,
     /// Message has exceeded max attempts and is in the dead letter queue.
     Dead,
+    /// Message was not reserved before its time-to-live passed.
+    Expired,
+    /// Message will never run because a message it depends on failed.
+    Cancelled,
+}
+
+impl FromSql for MessageState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "PENDING" => Ok(MessageState::Pending),
+            "RESERVED" => Ok(MessageState::Reserved),
+            "COMPLETED" => Ok(MessageState::Completed),
+            "DEAD" => Ok(MessageState::Dead),
+            "EXPIRED" => Ok(MessageState::Expired),
+            "CANCELLED" => Ok(MessageState::Cancelled),
+            other => Err(FromSqlError::Other(
+                format!("unknown message state {}", other).into(),
+            )),
+        }
+    }
 }
 
 impl MessageState {
@@ -65,8 +194,328 @@ impl MessageState {
             MessageState::Reserved => "RESERVED",
             MessageState::Completed => "COMPLETED",
             MessageState::Dead => "DEAD",
+            MessageState::Expired => "EXPIRED",
+            MessageState::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+/// A message reserved for processing, returned by [`QoxideQueue::reserve`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Reservation {
+    /// The message ID.
+    pub id: i64,
+    /// The message payload.
+    pub payload: Vec<u8>,
+    /// Proof of ownership, required to [`complete`](QoxideQueue::complete) or
+    /// [`fail`](QoxideQueue::fail) the message.
+    ///
+    /// A new token is issued each time the message is reserved, so once a reservation
+    /// is reclaimed or handed to another worker the old token stops working.
+    pub token: String,
+}
+
+/// A snapshot of a message and its bookkeeping, returned by [`QoxideQueue::get_message`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Message {
+    /// The message ID.
+    pub id: i64,
+    /// The name of the queue the message belongs to.
+    pub queue: String,
+    /// The current state of the message.
+    pub state: MessageState,
+    /// The message payload.
+    pub payload: Vec<u8>,
+    /// Number of failed attempts so far, including reclaimed reservations.
+    pub attempt_count: u32,
+    /// Higher priorities are reserved first.
+    pub priority: i64,
+    /// When the message was added. Messages added before this was recorded report the Unix epoch.
+    pub created_at: SystemTime,
+    /// Earliest time the message may be reserved.
+    pub available_at: SystemTime,
+    /// When the message was last reserved, if ever.
+    pub reserved_at: Option<SystemTime>,
+    /// When the current reservation times out, if reserved with a visibility timeout.
+    pub reserved_until: Option<SystemTime>,
+    /// When the message was completed, if it has been.
+    pub completed_at: Option<SystemTime>,
+    /// When the message expires if still pending, if it has a time-to-live and was never reserved.
+    pub expires_at: Option<SystemTime>,
+    /// Every reservation of the message, oldest first.
+    pub history: Vec<Attempt>,
+}
+
+/// A message's state and bookkeeping without its payload or history, returned by
+/// [`QoxideQueue::list`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct MessageSummary {
+    /// The message ID.
+    pub id: i64,
+    /// The current state of the message.
+    pub state: MessageState,
+    /// Number of failed attempts so far, including reclaimed reservations.
+    pub attempt_count: u32,
+    /// Higher priorities are reserved first.
+    pub priority: i64,
+    /// When the message was added. Messages added before this was recorded report the Unix epoch.
+    pub created_at: SystemTime,
+    /// Earliest time the message may be reserved.
+    pub available_at: SystemTime,
+    /// When the message was completed, if it has been.
+    pub completed_at: Option<SystemTime>,
+}
+
+/// One reservation of a message and how it ended, part of [`Message::history`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attempt {
+    /// The attempt number, starting at 1 for the first reservation.
+    pub number: u32,
+    /// When the message was reserved.
+    pub started_at: SystemTime,
+    /// When the attempt ended, `None` while it is in progress.
+    pub ended_at: Option<SystemTime>,
+    /// How the attempt ended, `None` while it is in progress.
+    pub outcome: Option<AttemptOutcome>,
+    /// The reason given to [`QoxideQueue::fail_with_reason`], if any.
+    pub error: Option<String>,
+}
+
+/// How an [`Attempt`] ended.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AttemptOutcome {
+    /// The message was completed.
+    Completed,
+    /// The message was failed.
+    Failed,
+    /// The reservation's visibility timeout passed and it was reclaimed.
+    TimedOut,
+}
+
+impl FromSql for AttemptOutcome {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "COMPLETED" => Ok(AttemptOutcome::Completed),
+            "FAILED" => Ok(AttemptOutcome::Failed),
+            "TIMED_OUT" => Ok(AttemptOutcome::TimedOut),
+            other => Err(FromSqlError::Other(
+                format!("unknown attempt outcome {}", other).into(),
+            )),
+        }
+    }
+}
+
+impl AttemptOutcome {
+    /// Returns the string representation of the outcome.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttemptOutcome::Completed => "COMPLETED",
+            AttemptOutcome::Failed => "FAILED",
+            AttemptOutcome::TimedOut => "TIMED_OUT",
+        }
+    }
+}
+
+/// Selects which messages [`QoxideQueue::list`] returns, one page at a time.
+///
+/// Messages are listed oldest first. Every condition is optional and they are
+/// combined with AND. To fetch the next page, pass the ID of the last message
+/// of the previous page to [`after`](Self::after).
+///
+/// # Example
+///
+/// ```
+/// use qoxide::{ListFilter, MessageState, QoxideQueue};
+///
+/// # fn main() -> Result<(), qoxide::Error> {
+/// let mut queue = QoxideQueue::new();
+/// queue.add_batch(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])?;
+///
+/// let filter = ListFilter::new().state(MessageState::Pending).limit(2);
+/// let page = queue.list(&filter)?;
+/// let next = queue.list(&filter.after(page[1].id))?;
+/// assert_eq!(page.len() + next.len(), 3);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ListFilter {
+    state: Option<MessageState>,
+    after: Option<i64>,
+    min_id: Option<i64>,
+    max_id: Option<i64>,
+    created_after: Option<SystemTime>,
+    created_before: Option<SystemTime>,
+    limit: usize,
+}
+
+impl ListFilter {
+    /// Creates a filter matching every message, limited to the default page size of 100.
+    pub fn new() -> Self {
+        Self {
+            state: None,
+            after: None,
+            min_id: None,
+            max_id: None,
+            created_after: None,
+            created_before: None,
+            limit: DEFAULT_LIST_LIMIT,
         }
     }
+
+    /// Only lists messages in `state`.
+    pub fn state(mut self, state: MessageState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Starts the page after the message with ID `cursor`.
+    pub fn after(mut self, cursor: i64) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Only lists messages with an ID of at least `id`.
+    pub fn min_id(mut self, id: i64) -> Self {
+        self.min_id = Some(id);
+        self
+    }
+
+    /// Only lists messages with an ID of at most `id`.
+    pub fn max_id(mut self, id: i64) -> Self {
+        self.max_id = Some(id);
+        self
+    }
+
+    /// Only lists messages created at or after `time`.
+    pub fn created_after(mut self, time: SystemTime) -> Self {
+        self.created_after = Some(time);
+        self
+    }
+
+    /// Only lists messages created before `time`.
+    pub fn created_before(mut self, time: SystemTime) -> Self {
+        self.created_before = Some(time);
+        self
+    }
+
+    /// Sets the maximum number of messages returned.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+impl Default for ListFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-message settings for [`QoxideQueue::add_with`], combined freely.
+///
+/// Each setting matches one of the `add_*` shortcuts, which are equivalent to
+/// `add_with` with just that setting.
+///
+/// # Example
+///
+/// ```
+/// use qoxide::{AddOptions, QoxideQueue};
+/// use std::time::Duration;
+///
+/// # fn main() -> Result<(), qoxide::Error> {
+/// let mut queue = QoxideQueue::new();
+/// let options = AddOptions::new()
+///     .delay(Duration::from_secs(60))
+///     .priority(5)
+///     .key("invoice-42");
+/// let id = queue.add_with(b"send invoice".to_vec(), options.clone())?;
+/// // The key is in flight, so the same ID comes back
+/// assert_eq!(queue.add_with(b"send invoice".to_vec(), options)?, id);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    available_at: i64,
+    priority: i64,
+    ttl: Option<Duration>,
+    key: Option<String>,
+    parents: Vec<i64>,
+}
+
+impl AddOptions {
+    /// Creates options for a message that is available immediately, with priority `0`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the message available for reservation after `delay`, like
+    /// [`add_delayed`](QoxideQueue::add_delayed).
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.available_at = now_millis().saturating_add(duration_millis(delay));
+        self
+    }
+
+    /// Makes the message available for reservation at `time`, like
+    /// [`add_at`](QoxideQueue::add_at).
+    pub fn at(mut self, time: SystemTime) -> Self {
+        self.available_at = system_time_millis(time);
+        self
+    }
+
+    /// Sets the message's priority, like [`add_with_priority`](QoxideQueue::add_with_priority).
+    pub fn priority(mut self, priority: i64) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the message's time-to-live, like [`add_with_ttl`](QoxideQueue::add_with_ttl).
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Suppresses the message if one with `key` is in flight, like
+    /// [`add_unique`](QoxideQueue::add_unique).
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Blocks the message until every message in `parents` is completed, like
+    /// [`add_after`](QoxideQueue::add_after).
+    pub fn after(mut self, parents: &[i64]) -> Self {
+        self.parents = parents.to_vec();
+        self
+    }
+}
+
+/// Settings for [`QoxideQueue::fail_with`], combining a failure reason with a retry delay.
+#[derive(Debug, Clone, Default)]
+pub struct FailOptions {
+    delay: Option<Duration>,
+    reason: Option<String>,
+}
+
+impl FailOptions {
+    /// Creates options that fail the message like [`fail`](QoxideQueue::fail).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retries the message no sooner than `delay` from now, instead of following the
+    /// queue's retry policy, like [`fail_with_delay`](QoxideQueue::fail_with_delay).
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Records why the attempt failed, like [`fail_with_reason`](QoxideQueue::fail_with_reason).
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
 }
 
 /// A breakdown of message counts by state.
@@ -82,6 +531,36 @@ pub struct QueueSize {
     pub completed: usize,
     /// Number of messages in the dead letter queue.
     pub dead: usize,
+    /// Number of messages that expired before being reserved.
+    pub expired: usize,
+    /// Number of messages cancelled because a message they depend on failed.
+    pub cancelled: usize,
+}
+
+/// A set of messages added together by [`QoxideQueue::add_group`].
+#[derive(Debug, PartialEq)]
+pub struct Group {
+    /// Group ID, used to look up its progress with [`QoxideQueue::group`].
+    pub id: i64,
+    /// IDs of the messages in the group, in the order they were added.
+    pub messages: Vec<i64>,
+}
+
+/// Progress of a [`Group`].
+#[derive(Debug, PartialEq)]
+pub struct GroupSummary {
+    /// Group ID.
+    pub id: i64,
+    /// Number of messages added to the group.
+    pub total: usize,
+    /// Number of members that completed.
+    pub succeeded: usize,
+    /// Number of members that died, expired, or were cancelled.
+    pub died: usize,
+    /// Number of members still pending or reserved.
+    pub remaining: usize,
+    /// ID of the callback message, `None` until every member has finished.
+    pub callback_id: Option<i64>,
 }
 
 /// Builder for creating a [`QoxideQueue`] with custom configuration.
@@ -96,10 +575,20 @@ pub struct QueueSize {
 ///     .max_attempts(3)
 ///     .build();
 /// ```
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct QoxideQueueBuilder {
     path: Option<String>,
+    name: Option<String>,
     max_attempts: Option<u32>,
+    visibility_timeout: Option<Duration>,
+    ttl: Option<Duration>,
+    drop_expired: bool,
+    deduplicate_payloads: bool,
+    dedup_window: Option<Duration>,
+    retention: Option<RetentionPolicy>,
+    retry_policy: Option<RetryPolicy>,
+    dead_parent_policy: DeadParentPolicy,
+    pool_size: Option<usize>,
 }
 
 impl QoxideQueueBuilder {
@@ -116,6 +605,17 @@ impl QoxideQueueBuilder {
         self
     }
 
+    /// Sets the name of the queue within the database.
+    ///
+    /// Queues with different names can share one database file without seeing
+    /// each other's messages.
+    ///
+    /// If not set, the queue is named `"default"`.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
     /// Sets the maximum number of attempts before moving to dead letter queue.
     ///
     /// For example, `max_attempts(3)` means a job can run at most 3 times.
@@ -127,17 +627,151 @@ impl QoxideQueueBuilder {
         self
     }
 
+    /// Sets how long a reserved message stays invisible to other workers.
+    ///
+    /// Once the timeout passes without the message being completed or failed,
+    /// it becomes reservable again and the abandoned reservation counts as a
+    /// failed attempt toward [`max_attempts`](Self::max_attempts).
+    ///
+    /// If not set, reserved messages stay reserved until explicitly completed or failed.
+    pub fn visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the default time-to-live for messages added to the queue.
+    ///
+    /// A pending message that is not reserved within its time-to-live expires.
+    /// The time-to-live starts once the message becomes available, so delayed
+    /// messages do not expire before they can be reserved.
+    /// Use [`add_with_ttl`](QoxideQueue::add_with_ttl) to override it per message.
+    ///
+    /// If not set, messages never expire.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets whether expired messages are deleted instead of kept.
+    ///
+    /// By default expired messages move to the `Expired` state so they can be inspected.
+    pub fn drop_expired(mut self, drop_expired: bool) -> Self {
+        self.drop_expired = drop_expired;
+        self
+    }
+
+    /// Sets whether messages are deduplicated by a hash of their payload.
+    ///
+    /// When enabled, adding a payload identical to one that is already pending or
+    /// reserved returns the existing message ID instead of enqueueing a duplicate.
+    /// See [`add_unique`](QoxideQueue::add_unique) for deduplication by an explicit key.
+    pub fn deduplicate_payloads(mut self, deduplicate_payloads: bool) -> Self {
+        self.deduplicate_payloads = deduplicate_payloads;
+        self
+    }
+
+    /// Sets how long completed messages keep suppressing duplicates.
+    ///
+    /// A duplicate of a message completed within the window is not enqueued again.
+    ///
+    /// If not set, only pending and reserved messages suppress duplicates.
+    pub fn dedup_window(mut self, window: Duration) -> Self {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Sets how long failed messages wait before they can be reserved again.
+    ///
+    /// The wait only applies when [`fail`](QoxideQueue::fail) returns the message
+    /// to pending. Use [`fail_with_delay`](QoxideQueue::fail_with_delay) to choose
+    /// the wait for a single failure.
+    ///
+    /// If not set, failed messages can be reserved again immediately.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Sets what happens to messages waiting on a parent that can no longer complete.
+    ///
+    /// If not set, they are left blocked.
+    pub fn dead_parent_policy(mut self, policy: DeadParentPolicy) -> Self {
+        self.dead_parent_policy = policy;
+        self
+    }
+
+    /// Sets how long completed messages are kept.
+    ///
+    /// Completed messages the policy no longer keeps are deleted whenever a message
+    /// is reserved or completed.
+    ///
+    /// If not set, completed messages are kept until removed or purged.
+    pub fn retention(mut self, policy: RetentionPolicy) -> Self {
+        self.retention = Some(policy);
+        self
+    }
+
+    /// Sets the maximum number of connections held by a [`SharedQoxideQueue`].
+    ///
+    /// Only used by [`build_shared`](Self::build_shared). In-memory queues always
+    /// use a single connection, since each connection would see its own database.
+    ///
+    /// If not set, the pool holds up to 4 connections.
+    pub fn pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = Some(pool_size);
+        self
+    }
+
+    /// Builds a thread-safe handle backed by a pool of connections with the configured settings.
+    pub fn build_shared(self) -> Result<SharedQoxideQueue, Error> {
+        SharedQoxideQueue::new(self)
+    }
+
+    /// Builds an async handle backed by a pool of connections with the configured settings.
+    ///
+    /// Opening the first connection blocks the calling thread.
+    #[cfg(feature = "async")]
+    pub fn build_async(self) -> Result<AsyncQoxideQueue, Error> {
+        self.build_shared().map(AsyncQoxideQueue::new)
+    }
+
     /// Builds the queue with the configured settings.
+    ///
+    /// Returns [`Error::Config`] if a setting is out of range.
     pub fn build(self) -> Result<QoxideQueue, Error> {
+        self.validate()?;
         let path = self.path.as_deref().unwrap_or(":memory:");
         let db = Connection::open(path)?;
         let queue = QoxideQueue {
             db,
+            name: self.name.unwrap_or_else(|| DEFAULT_QUEUE_NAME.to_string()),
             max_attempts: self.max_attempts,
+            visibility_timeout: self.visibility_timeout,
+            ttl: self.ttl,
+            drop_expired: self.drop_expired,
+            deduplicate_payloads: self.deduplicate_payloads,
+            dedup_window: self.dedup_window,
+            retention: self.retention,
+            retry_policy: self.retry_policy,
+            dead_parent_policy: self.dead_parent_policy,
+            notifier: Notifier::for_path(path),
         };
         queue.init(path)?;
         Ok(queue)
     }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.name.as_deref() == Some("") {
+            return Err(Error::Config("queue name must not be empty".to_string()));
+        }
+        if self.max_attempts == Some(0) {
+            return Err(Error::Config("max_attempts must be at least 1".to_string()));
+        }
+        if self.pool_size == Some(0) {
+            return Err(Error::Config("pool_size must be at least 1".to_string()));
+        }
+        Ok(())
+    }
 }
 
 impl Default for QoxideQueue {
@@ -168,23 +802,79 @@ impl QoxideQueue {
         QoxideQueueBuilder::new()
     }
 
+    /// Returns the name of the queue within the database.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     fn init(&self, path: &str) -> Result<(), Error> {
         if path != ":memory:" {
             self.db.execute_batch(
-                "PRAGMA journal_mode=WAL;
-                 PRAGMA busy_timeout=5000;",
+                "PRAGMA busy_timeout=5000;
+                 PRAGMA journal_mode=WAL;",
             )?;
         }
 
-        self.db.execute_batch(include_str!("init.sql"))
+        self.migrate()?;
+        self.db.execute_batch(include_str!("init.sql"))?;
+        Ok(())
+    }
+
+    /// Adds columns introduced after the initial schema to databases created by older versions.
+    fn migrate(&self) -> Result<(), Error> {
+        let columns = self.table_columns("messages")?;
+        // A fresh database gets the full schema from init.sql
+        if columns.is_empty() {
+            return Ok(());
+        }
+
+        self.add_missing_columns("messages", &columns, MESSAGE_COLUMNS)?;
+        if !columns.iter().any(|column| column == "completed_at") {
+            // Age messages completed before completion times were recorded from when they
+            // were added, so retention and purge can still delete them
+            self.db.execute(
+                "UPDATE messages SET completed_at = created_at WHERE state = 'COMPLETED'",
+                [],
+            )?;
+        }
+        let payload_columns = self.table_columns("payloads")?;
+        self.add_missing_columns("payloads", &payload_columns, PAYLOAD_COLUMNS)
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<String>, Error> {
+        let mut statement = self.db.prepare("SELECT name FROM pragma_table_info(?)")?;
+        let columns = statement
+            .query_map(params![table], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(columns)
+    }
+
+    fn add_missing_columns(
+        &self,
+        table: &str,
+        existing: &[String],
+        columns: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        for (name, definition) in columns {
+            if !existing.iter().any(|column| column == name) {
+                self.db.execute(
+                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table, name, definition),
+                    [],
+                )?;
+            }
+        }
+        Ok(())
     }
 
     /// Returns the count of messages in each state.
+    ///
+    /// Pending messages past their time-to-live are expired first.
     pub fn size(&self) -> Result<QueueSize, Error> {
-        let mut statement = self
-            .db
-            .prepare_cached("SELECT state, COUNT(1) AS count FROM messages GROUP BY state")?;
-        let mut rows = statement.query([])?;
+        self.sweep()?;
+        let mut statement = self.db.prepare_cached(
+            "SELECT state, COUNT(1) AS count FROM messages WHERE queue = ? GROUP BY state",
+        )?;
+        let mut rows = statement.query(params![self.name])?;
         let mut total: usize = 0;
         let mut sizes = QueueSize {
             total: 0,
@@ -192,6 +882,8 @@ impl QoxideQueue {
             reserved: 0,
             completed: 0,
             dead: 0,
+            expired: 0,
+            cancelled: 0,
         };
         while let Some(row) = rows.next()? {
             let state: String = row.get(0)?;
@@ -202,6 +894,8 @@ impl QoxideQueue {
                 "RESERVED" => sizes.reserved = count,
                 "COMPLETED" => sizes.completed = count,
                 "DEAD" => sizes.dead = count,
+                "EXPIRED" => sizes.expired = count,
+                "CANCELLED" => sizes.cancelled = count,
                 _ => (),
             }
         }
@@ -211,64 +905,519 @@ impl QoxideQueue {
     }
 
     /// Returns the payload for a message by ID.
+    ///
+    /// Returns [`Error::NotFound`] if the message does not exist in this queue.
     pub fn get(&self, id: i64) -> Result<Vec<u8>, Error> {
-        self.db.query_row(
-            "SELECT p.data FROM messages m JOIN payloads p ON m.payload_id = p.id WHERE m.id = ?",
-            params![id],
-            |row| row.get(0),
-        )
+        self.db
+            .query_row(
+                "SELECT p.data FROM messages m JOIN payloads p ON m.payload_id = p.id
+                 WHERE m.id = ? AND m.queue = ?",
+                params![id, self.name],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(Error::NotFound(id))
+    }
+
+    /// Returns a message with its state, attempt count, timestamps, and attempt history.
+    ///
+    /// Pending messages past their time-to-live are expired first, so they are
+    /// reported as expired.
+    /// Returns [`Error::NotFound`] if the message does not exist in this queue.
+    pub fn get_message(&self, id: i64) -> Result<Message, Error> {
+        self.sweep()?;
+        let mut message = self
+            .db
+            .query_row(
+                &format!("{} WHERE m.id = ? AND m.queue = ?", MESSAGE_SELECT),
+                params![id, self.name],
+                message_from_row,
+            )
+            .optional()?
+            .ok_or(Error::NotFound(id))?;
+        message.history = attempt_history(&self.db, id)?;
+        Ok(message)
     }
 
     /// Adds a message to the queue with the given payload.
     ///
     /// Returns the message ID which can be used with [`complete`](Self::complete) or [`fail`](Self::fail).
     pub fn add(&mut self, payload: Vec<u8>) -> Result<i64, Error> {
-        let transaction = self.db.transaction()?;
-        transaction.execute("INSERT INTO payloads (data) VALUES (?);", params![&payload])?;
-        let payload_id = transaction.last_insert_rowid();
+        self.enqueue(payload, NewMessage::default())
+    }
+
+    /// Adds a message with any combination of a delay, priority, time-to-live,
+    /// deduplication key, and parents.
+    ///
+    /// Returns the existing message's ID if the key is in flight, or
+    /// [`Error::NotFound`] if a parent does not exist in this queue.
+    pub fn add_with(&mut self, payload: Vec<u8>, options: AddOptions) -> Result<i64, Error> {
+        self.enqueue(payload, options.into())
+    }
+
+    /// Adds a message that becomes available for reservation after `delay`.
+    ///
+    /// The message is counted as pending in the meantime, but [`reserve`](Self::reserve)
+    /// skips it until the delay has passed.
+    pub fn add_delayed(&mut self, payload: Vec<u8>, delay: Duration) -> Result<i64, Error> {
+        self.add_with(payload, AddOptions::new().delay(delay))
+    }
+
+    /// Adds a message that becomes available for reservation at `time`.
+    ///
+    /// A time in the past makes the message available immediately.
+    pub fn add_at(&mut self, payload: Vec<u8>, time: SystemTime) -> Result<i64, Error> {
+        self.add_with(payload, AddOptions::new().at(time))
+    }
+
+    /// Adds a message with the given priority.
+    ///
+    /// Messages with a higher priority are reserved before those with a lower one.
+    /// Messages added with [`add`](Self::add) have priority `0`.
+    pub fn add_with_priority(&mut self, payload: Vec<u8>, priority: i64) -> Result<i64, Error> {
+        self.add_with(payload, AddOptions::new().priority(priority))
+    }
+
+    /// Adds a message that expires if not reserved within `ttl`.
+    ///
+    /// Overrides the queue's default [`ttl`](QoxideQueueBuilder::ttl).
+    pub fn add_with_ttl(&mut self, payload: Vec<u8>, ttl: Duration) -> Result<i64, Error> {
+        self.add_with(payload, AddOptions::new().ttl(ttl))
+    }
+
+    /// Adds a message unless one with the same key is already in flight.
+    ///
+    /// If a message with `key` is pending or reserved, or was completed within the
+    /// [`dedup_window`](QoxideQueueBuilder::dedup_window), its ID is returned and
+    /// nothing is enqueued. This makes retried producers safe to repeat.
+    pub fn add_unique(&mut self, key: &str, payload: Vec<u8>) -> Result<i64, Error> {
+        self.add_with(payload, AddOptions::new().key(key))
+    }
+
+    /// Adds a message that stays blocked until every message in `parents` is completed.
+    ///
+    /// Blocked messages count as pending but are skipped by [`reserve`](Self::reserve).
+    /// If a parent dies, expires, or is cancelled, the message is handled according
+    /// to the queue's [`dead_parent_policy`](QoxideQueueBuilder::dead_parent_policy).
+    /// A parent removed with [`remove`](Self::remove) no longer blocks the message, but
+    /// one deleted by [`purge`](Self::purge) or `drop_expired` before completing counts
+    /// as failed.
+    ///
+    /// Returns [`Error::NotFound`] if a parent does not exist in this queue.
+    pub fn add_after(&mut self, payload: Vec<u8>, parents: &[i64]) -> Result<i64, Error> {
+        self.add_with(payload, AddOptions::new().after(parents))
+    }
+
+    /// Adds many messages in a single transaction.
+    ///
+    /// Either every payload is added or none are. Returns the message IDs in the
+    /// same order as the payloads.
+    pub fn add_batch(&mut self, payloads: Vec<Vec<u8>>) -> Result<Vec<i64>, Error> {
+        self.enqueue_all(
+            payloads
+                .into_iter()
+                .map(|payload| (payload, NewMessage::default())),
+        )
+    }
+
+    /// Adds `n` messages that share a single stored copy of `payload`.
+    ///
+    /// Use this to hand the same large payload to many jobs. Every message is
+    /// enqueued even if [`deduplicate_payloads`](QoxideQueueBuilder::deduplicate_payloads)
+    /// is enabled, though later adds of the same payload are then suppressed while
+    /// any of them is in flight. Returns the message IDs in the order they were added.
+    pub fn fan_out(&mut self, payload: Vec<u8>, n: usize) -> Result<Vec<i64>, Error> {
+        let now = now_millis();
+        let transaction = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let hash = payload_hash(&payload);
+        let payload_id = store_payload(&transaction, &payload, &hash)?;
+        // Recorded so later adds of the same payload are suppressed while these are in flight
+        let message = NewMessage {
+            dedup_key: self.deduplicate_payloads.then_some(hash),
+            ..NewMessage::default()
+        };
+        let ids = (0..n)
+            .map(|_| {
+                insert_message(
+                    &transaction,
+                    &self.name,
+                    payload_id,
+                    &message,
+                    self.ttl,
+                    now,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        transaction.commit()?;
+        self.notifier.notify();
+        Ok(ids)
+    }
+
+    /// Adds a group of messages, and a callback message once every member has finished.
+    ///
+    /// A member finishes when it completes, dies, expires, or is cancelled. The callback
+    /// is added to `callback_queue`, which may be this queue, with a JSON payload such as
+    /// `{"group":1,"succeeded":9,"died":1}`. Every member is enqueued even if
+    /// [`deduplicate_payloads`](QoxideQueueBuilder::deduplicate_payloads) is enabled,
+    /// though later adds of a member's payload are suppressed while it is in flight.
+    ///
+    /// Returns [`Error::Config`] if `callback_queue` is empty.
+    pub fn add_group(
+        &mut self,
+        payloads: Vec<Vec<u8>>,
+        callback_queue: &str,
+    ) -> Result<Group, Error> {
+        if callback_queue.is_empty() {
+            return Err(Error::Config(
+                "callback queue name must not be empty".to_string(),
+            ));
+        }
+        let now = now_millis();
+        let transaction = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
         transaction.execute(
-            "INSERT INTO messages (state, payload_id) VALUES (?, ?);",
-            params![MessageState::Pending.as_str(), payload_id],
+            "INSERT INTO groups (queue, callback_queue, total, created_at) VALUES (?, ?, ?, ?)",
+            params![self.name, callback_queue, payloads.len(), now],
         )?;
-        let message_id = transaction.last_insert_rowid();
+        let group_id = transaction.last_insert_rowid();
+        let mut messages = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let hash = payload_hash(&payload);
+            let payload_id = store_payload(&transaction, &payload, &hash)?;
+            // Recorded so later adds of the same payload are suppressed while this is in flight
+            let message = NewMessage {
+                group_id: Some(group_id),
+                dedup_key: self.deduplicate_payloads.then_some(hash),
+                ..NewMessage::default()
+            };
+            messages.push(insert_message(
+                &transaction,
+                &self.name,
+                payload_id,
+                &message,
+                self.ttl,
+                now,
+            )?);
+        }
+        // An empty group has nothing to wait for
+        finish_groups(&transaction, &self.name, now)?;
         transaction.commit()?;
-        Ok(message_id)
+        self.notifier.notify();
+        Ok(Group {
+            id: group_id,
+            messages,
+        })
+    }
+
+    /// Returns how many members of a group have succeeded, died, or are still remaining.
+    ///
+    /// Members removed before the group finished are not counted as succeeded or died.
+    /// Returns [`Error::GroupNotFound`] if the group does not exist in this queue.
+    pub fn group(&self, id: i64) -> Result<GroupSummary, Error> {
+        let (total, callback_id) = self
+            .db
+            .query_row(
+                "SELECT total, callback_id FROM groups WHERE id = ? AND queue = ?",
+                params![id, self.name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or(Error::GroupNotFound(id))?;
+        let (succeeded, died, remaining) = group_counts(&self.db, id)?;
+        Ok(GroupSummary {
+            id,
+            total,
+            succeeded,
+            died,
+            remaining,
+            callback_id,
+        })
+    }
+
+    fn enqueue(&mut self, payload: Vec<u8>, message: NewMessage) -> Result<i64, Error> {
+        let ids = self.enqueue_all([(payload, message)])?;
+        Ok(ids[0])
+    }
+
+    fn enqueue_all(
+        &mut self,
+        messages: impl IntoIterator<Item = (Vec<u8>, NewMessage)>,
+    ) -> Result<Vec<i64>, Error> {
+        let now = now_millis();
+        let completed_after = self
+            .dedup_window
+            .map(|window| now.saturating_sub(duration_millis(window)));
+        // Immediate so the duplicate check and insert cannot interleave with another writer
+        let transaction = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut ids = Vec::new();
+        for (payload, mut message) in messages {
+            let hash = payload_hash(&payload);
+            if message.dedup_key.is_none() && self.deduplicate_payloads {
+                message.dedup_key = Some(hash.clone());
+            }
+            if let Some(key) = &message.dedup_key {
+                let existing = transaction
+                    .prepare_cached(
+                        "SELECT id FROM messages
+                         WHERE queue = ? AND dedup_key = ?
+                           AND (state IN ('PENDING', 'RESERVED')
+                                OR (state = 'COMPLETED' AND completed_at >= ?))
+                         ORDER BY id DESC
+                         LIMIT 1",
+                    )?
+                    .query_row(params![self.name, key, completed_after], |row| row.get(0))
+                    .optional()?;
+                if let Some(id) = existing {
+                    ids.push(id);
+                    continue;
+                }
+            }
+
+            for &parent in &message.parents {
+                message_state(&transaction, &self.name, parent)?;
+            }
+            let payload_id = store_payload(&transaction, &payload, &hash)?;
+            let id = insert_message(
+                &transaction,
+                &self.name,
+                payload_id,
+                &message,
+                self.ttl,
+                now,
+            )?;
+            for &parent in &message.parents {
+                transaction
+                    .prepare_cached(
+                        "INSERT OR IGNORE INTO dependencies (message_id, parent_id) VALUES (?, ?)",
+                    )?
+                    .execute(params![id, parent])?;
+            }
+            ids.push(id);
+        }
+        transaction.commit()?;
+        self.notifier.notify();
+        Ok(ids)
     }
 
     /// Atomically reserves the next pending message.
     ///
-    /// Returns the message ID and payload. The message state changes from `Pending` to `Reserved`.
-    /// Returns an error if no pending messages are available.
-    pub fn reserve(&mut self) -> Result<(i64, Vec<u8>), Error> {
-        let tx = self.db.transaction()?;
+    /// Messages are reserved highest priority first, and oldest first within a priority.
+    ///
+    /// Returns the message ID, payload, and reservation token. The message state changes
+    /// from `Pending` to `Reserved`.
+    /// Returns [`Error::Empty`] if no pending messages are available, including when every
+    /// pending message is delayed until a later time.
+    ///
+    /// Reservations whose visibility timeout has passed are reclaimed first,
+    /// and pending messages past their time-to-live are expired.
+    pub fn reserve(&mut self) -> Result<Reservation, Error> {
+        self.reserve_batch(1)?.pop().ok_or(Error::Empty)
+    }
 
-        let (id, payload_id): (i64, i64) = tx.query_row(
-            "SELECT id, payload_id FROM messages WHERE state = 'PENDING' LIMIT 1",
-            [],
-            |row| Ok((row.get(0)?, row.get(1)?)),
-        )?;
+    /// Reserves the next pending message, waiting up to `timeout` for one to arrive.
+    ///
+    /// Adding a message from any handle on the same database in this process wakes
+    /// the waiting thread immediately. Messages added by other processes, and delayed
+    /// messages becoming available, are picked up by polling every 100ms.
+    ///
+    /// Returns [`Error::Empty`] if no message becomes available before the timeout.
+    pub fn reserve_wait(&mut self, timeout: Duration) -> Result<Reservation, Error> {
+        let notifier = Arc::clone(&self.notifier);
+        notifier.wait_for(timeout, || self.reserve())
+    }
 
-        tx.execute(
-            "UPDATE messages SET state = 'RESERVED' WHERE id = ?",
-            params![id],
+    /// Atomically reserves up to `n` pending messages.
+    ///
+    /// Messages are returned in the same order [`reserve`](Self::reserve) would return them.
+    /// Returns an empty list if no pending messages are available.
+    pub fn reserve_batch(&mut self, n: usize) -> Result<Vec<Reservation>, Error> {
+        let now = now_millis();
+        let reserved_until = self
+            .visibility_timeout
+            .map(|timeout| now.saturating_add(duration_millis(timeout)));
+        // Immediate so concurrent connections queue up instead of failing to upgrade their lock,
+        // and so housekeeping cannot interleave with a complete or heartbeat from another connection
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        reclaim_expired(&tx, &self.name, self.max_attempts, self.retry_policy, now)?;
+        expire_pending(
+            &tx,
+            &self.name,
+            self.drop_expired,
+            self.dead_parent_policy,
+            now,
         )?;
+        apply_retention(&tx, &self.name, self.retention, now)?;
+        cancel_blocked_children(&tx, &self.name, self.dead_parent_policy)?;
+        // Members that expired or were cancelled may have finished a group
+        let callbacks = finish_groups(&tx, &self.name, now)?;
 
-        let payload: Vec<u8> = tx.query_row(
-            "SELECT data FROM payloads WHERE id = ?",
-            params![payload_id],
-            |row| row.get(0),
-        )?;
+        // Selecting and claiming in one statement means no other connection can claim
+        // the same messages between the two, and the subquery's order picks the oldest
+        let mut claimed = tx
+            .prepare_cached(
+                "UPDATE messages
+                 SET state = 'RESERVED', reserved_at = ?1, reserved_until = ?2,
+                     reservation_token = lower(hex(randomblob(16))),
+                     expires_at = NULL
+                 WHERE id IN (
+                     SELECT id FROM messages
+                     WHERE queue = ?3 AND state = 'PENDING' AND available_at <= ?1
+                       AND NOT EXISTS (
+                           SELECT 1 FROM dependencies d LEFT JOIN messages parent ON parent.id = d.parent_id
+                           WHERE d.message_id = messages.id
+                             AND (parent.state IS NULL OR parent.state != 'COMPLETED')
+                       )
+                     ORDER BY priority DESC, id
+                     LIMIT ?4
+                 )
+                 RETURNING id, priority, payload_id, reservation_token",
+            )?
+            .query_map(
+                params![
+                    now,
+                    reserved_until,
+                    self.name,
+                    i64::try_from(n).unwrap_or(i64::MAX)
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        // RETURNING does not preserve the subquery's order
+        claimed.sort_by_key(|&(id, priority, _, _)| (std::cmp::Reverse(priority), id));
+
+        let mut reserved = Vec::with_capacity(claimed.len());
+        for (id, _, payload_id, token) in claimed {
+            tx.prepare_cached(
+                "INSERT INTO attempts (message_id, attempt, started_at)
+                 SELECT ?1, COALESCE(MAX(attempt), 0) + 1, ?2 FROM attempts WHERE message_id = ?1",
+            )?
+            .execute(params![id, now])?;
+            let payload: Vec<u8> = tx
+                .prepare_cached("SELECT data FROM payloads WHERE id = ?")?
+                .query_row(params![payload_id], |row| row.get(0))?;
+            reserved.push(Reservation { id, payload, token });
+        }
 
         tx.commit()?;
-        Ok((id, payload))
+        if callbacks > 0 {
+            self.notifier.notify();
+        }
+        Ok(reserved)
     }
 
     /// Marks a reserved message as successfully completed.
-    pub fn complete(&self, id: i64) -> Result<(), Error> {
-        self.db.execute(
-            "UPDATE messages SET state = ? WHERE id = ?",
-            params![MessageState::Completed.as_str(), id],
+    ///
+    /// Completed messages are kept according to the queue's
+    /// [`retention`](QoxideQueueBuilder::retention) policy.
+    ///
+    /// `token` must be the token returned when the message was reserved.
+    ///
+    /// Returns [`Error::NotFound`] if the message does not exist,
+    /// [`Error::StaleReservation`] if `token` no longer owns the message, or
+    /// [`Error::InvalidTransition`] if it is not reserved.
+    pub fn complete(&mut self, id: i64, token: &str) -> Result<(), Error> {
+        let now = now_millis();
+        // Immediate so finishing the message and its group cannot interleave with another writer
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let updated = tx.execute(
+            "UPDATE messages SET state = ?, reserved_until = NULL, completed_at = ?
+             WHERE id = ? AND queue = ? AND state = 'RESERVED' AND reservation_token = ?",
+            params![MessageState::Completed.as_str(), now, id, self.name, token],
         )?;
+        if updated == 0 {
+            let from = owned_state(&tx, &self.name, id, token)?;
+            return Err(Error::InvalidTransition {
+                from,
+                to: MessageState::Completed,
+            });
+        }
+        end_attempt(&tx, id, AttemptOutcome::Completed, None, now)?;
+        let callbacks = finish_groups(&tx, &self.name, now)?;
+        apply_retention(&tx, &self.name, self.retention, now)?;
+        tx.commit()?;
+        if callbacks > 0 {
+            self.notifier.notify();
+        }
+        Ok(())
+    }
+
+    /// Keeps a reservation alive for `duration` from now, replacing its current deadline.
+    ///
+    /// Long-running jobs call this periodically so the visibility timeout does not
+    /// hand their message to another worker. The deadline applies even if the queue
+    /// has no visibility timeout.
+    ///
+    /// Returns [`Error::NotFound`] if the message does not exist,
+    /// [`Error::StaleReservation`] if `token` no longer owns the message, or
+    /// [`Error::InvalidTransition`] if it is not reserved.
+    pub fn extend(&self, id: i64, token: &str, duration: Duration) -> Result<(), Error> {
+        let reserved_until = now_millis().saturating_add(duration_millis(duration));
+        self.renew(id, token, Some(reserved_until))
+    }
+
+    /// Keeps a reservation alive for another visibility timeout from now.
+    ///
+    /// Without a visibility timeout this only checks that `token` still owns the
+    /// message, leaving any deadline set by [`extend`](Self::extend) in place.
+    /// See [`extend`](Self::extend) for errors.
+    pub fn heartbeat(&self, id: i64, token: &str) -> Result<(), Error> {
+        let reserved_until = self
+            .visibility_timeout
+            .map(|timeout| now_millis().saturating_add(duration_millis(timeout)));
+        self.renew(id, token, reserved_until)
+    }
+
+    /// Expires, cancels, and finishes groups for the read-only methods.
+    fn sweep(&self) -> Result<(), Error> {
+        let now = now_millis();
+        // The read-only methods only have `&self`; SQLite still refuses a nested BEGIN
+        let tx = self.db.unchecked_transaction()?;
+        expire_pending(
+            &tx,
+            &self.name,
+            self.drop_expired,
+            self.dead_parent_policy,
+            now,
+        )?;
+        cancel_blocked_children(&tx, &self.name, self.dead_parent_policy)?;
+        let callbacks = finish_groups(&tx, &self.name, now)?;
+        tx.commit()?;
+        if callbacks > 0 {
+            self.notifier.notify();
+        }
+        Ok(())
+    }
+
+    /// Moves the reservation deadline to `reserved_until`, or only checks ownership if `None`.
+    fn renew(&self, id: i64, token: &str, reserved_until: Option<i64>) -> Result<(), Error> {
+        let updated = self.db.execute(
+            "UPDATE messages SET reserved_until = COALESCE(?, reserved_until)
+             WHERE id = ? AND queue = ? AND state = 'RESERVED' AND reservation_token = ?",
+            params![reserved_until, id, self.name, token],
+        )?;
+        if updated == 0 {
+            let from = owned_state(&self.db, &self.name, id, token)?;
+            return Err(Error::InvalidTransition {
+                from,
+                to: MessageState::Reserved,
+            });
+        }
         Ok(())
     }
 
@@ -278,59 +1427,687 @@ impl QoxideQueue {
     /// If the queue has a max attempts limit and this was the final attempt,
     /// the message moves to the dead letter queue.
     ///
-    /// Returns the new state of the message.
-    pub fn fail(&mut self, id: i64) -> Result<MessageState, Error> {
+    /// A message returned to pending can be reserved again once the queue's
+    /// [`retry_policy`](QoxideQueueBuilder::retry_policy) delay has passed.
+    ///
+    /// `token` must be the token returned when the message was reserved.
+    ///
+    /// Returns the new state of the message, [`Error::NotFound`] if the message
+    /// does not exist, [`Error::StaleReservation`] if `token` no longer owns the
+    /// message, or [`Error::InvalidTransition`] if it is not reserved.
+    pub fn fail(&mut self, id: i64, token: &str) -> Result<MessageState, Error> {
+        self.fail_after(id, token, None, None)
+    }
+
+    /// Marks a reserved message as failed, recording `reason` in its attempt history.
+    ///
+    /// Behaves like [`fail`](Self::fail). The reason is shown by
+    /// [`get_message`](Self::get_message), to explain why a message ended up in the
+    /// dead letter queue.
+    pub fn fail_with_reason(
+        &mut self,
+        id: i64,
+        token: &str,
+        reason: &str,
+    ) -> Result<MessageState, Error> {
+        self.fail_after(id, token, None, Some(reason))
+    }
+
+    /// Marks a reserved message as failed, retrying it no sooner than `delay` from now.
+    ///
+    /// Behaves like [`fail`](Self::fail), except `delay` replaces the queue's retry policy.
+    pub fn fail_with_delay(
+        &mut self,
+        id: i64,
+        token: &str,
+        delay: Duration,
+    ) -> Result<MessageState, Error> {
+        self.fail_after(id, token, Some(delay), None)
+    }
+
+    /// Marks a reserved message as failed, with a reason and retry delay from `options`.
+    ///
+    /// Behaves like [`fail`](Self::fail), recording the reason like
+    /// [`fail_with_reason`](Self::fail_with_reason) and delaying the retry like
+    /// [`fail_with_delay`](Self::fail_with_delay).
+    pub fn fail_with(
+        &mut self,
+        id: i64,
+        token: &str,
+        options: FailOptions,
+    ) -> Result<MessageState, Error> {
+        self.fail_after(id, token, options.delay, options.reason.as_deref())
+    }
+
+    fn fail_after(
+        &mut self,
+        id: i64,
+        token: &str,
+        delay: Option<Duration>,
+        reason: Option<&str>,
+    ) -> Result<MessageState, Error> {
+        let now = now_millis();
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let state = owned_state(&tx, &self.name, id, token)?;
+        let attempt_count: u32 = tx.query_row(
+            "SELECT attempt_count + 1 FROM messages WHERE id = ?",
+            params![id],
+            |row| row.get(0),
+        )?;
         let new_state = match self.max_attempts {
-            None => MessageState::Pending,
-            Some(max) => {
-                let attempt_count: u32 = self.db.query_row(
-                    "SELECT attempt_count FROM messages WHERE id = ?",
-                    params![id],
-                    |row| row.get::<_, u32>(0).map(|c| c + 1),
-                )?;
-                if attempt_count >= max {
-                    MessageState::Dead
-                } else {
-                    MessageState::Pending
-                }
-            }
+            Some(max) if attempt_count >= max => MessageState::Dead,
+            _ => MessageState::Pending,
         };
+        if state != MessageState::Reserved {
+            return Err(Error::InvalidTransition {
+                from: state,
+                to: new_state,
+            });
+        }
 
-        self.db.execute(
-            "UPDATE messages SET state = ?, attempt_count = attempt_count + 1 WHERE id = ?",
-            params![new_state.as_str(), id],
+        let delay = delay.or_else(|| self.retry_policy.map(|policy| policy.delay(attempt_count)));
+        let retry_at = delay
+            .filter(|_| new_state == MessageState::Pending)
+            .map(|delay| now.saturating_add(duration_millis(delay)));
+        tx.execute(
+            "UPDATE messages
+             SET state = ?, attempt_count = attempt_count + 1, reserved_until = NULL,
+                 available_at = COALESCE(?, available_at)
+             WHERE id = ? AND queue = ?",
+            params![new_state.as_str(), retry_at, id, self.name],
         )?;
+        end_attempt(&tx, id, AttemptOutcome::Failed, reason, now)?;
+        let mut callbacks = 0;
+        if new_state == MessageState::Dead {
+            cancel_blocked_children(&tx, &self.name, self.dead_parent_policy)?;
+            callbacks = finish_groups(&tx, &self.name, now)?;
+        }
+        tx.commit()?;
+        if new_state == MessageState::Pending || callbacks > 0 {
+            self.notifier.notify();
+        }
 
         Ok(new_state)
     }
 
-    /// Removes a message by ID permanently.
+    /// Removes a message by ID permanently, whatever its state.
+    ///
+    /// Messages added with [`add_after`](Self::add_after) no longer wait for it.
+    ///
+    /// Returns [`Error::NotFound`] if the message does not exist.
     pub fn remove(&mut self, id: i64) -> Result<(), Error> {
-        self.db
-            .execute("DELETE FROM messages WHERE id = ?", params![id])?;
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let deleted = tx.execute(
+            "DELETE FROM messages WHERE id = ? AND queue = ?",
+            params![id, self.name],
+        )?;
+        if deleted == 0 {
+            return Err(Error::NotFound(id));
+        }
+        tx.execute("DELETE FROM dependencies WHERE parent_id = ?", params![id])?;
+        tx.commit()?;
         Ok(())
     }
 
+    /// Deletes every message in `state` older than `older_than`, returning how many were deleted.
+    ///
+    /// Completed messages are aged from when they were completed, and messages in
+    /// any other state from when they were added. Purging reserved messages deletes
+    /// them from under their workers, whose tokens then return [`Error::NotFound`].
+    pub fn purge(&mut self, state: MessageState, older_than: Duration) -> Result<usize, Error> {
+        let cutoff = now_millis().saturating_sub(duration_millis(older_than));
+        let deleted = self.db.execute(
+            "DELETE FROM messages
+             WHERE queue = ?1 AND state = ?2
+               AND CASE WHEN state = 'COMPLETED' THEN completed_at ELSE created_at END <= ?3",
+            params![self.name, state.as_str(), cutoff],
+        )?;
+        Ok(deleted)
+    }
+
+    /// Reclaims unused space in the database file, returning how many bytes were freed.
+    ///
+    /// Payloads are deleted along with the last message using them, but SQLite keeps
+    /// the freed pages for reuse instead of shrinking the file. This deletes payloads
+    /// orphaned by older versions, then rebuilds the database with `VACUUM`.
+    ///
+    /// Compaction covers the whole database file, including other named queues, and
+    /// blocks every other connection while it runs.
+    pub fn compact(&mut self) -> Result<u64, Error> {
+        let before = database_size(&self.db)?;
+        self.db.execute(
+            "DELETE FROM payloads WHERE id NOT IN (SELECT payload_id FROM messages)",
+            [],
+        )?;
+        self.db.execute_batch("VACUUM;")?;
+        // Copy the rebuilt pages back from the WAL so the file itself shrinks
+        self.db
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        let after = database_size(&self.db)?;
+        Ok(before.saturating_sub(after))
+    }
+
+    /// Returns a page of message summaries matching `filter`, oldest first.
+    ///
+    /// Summaries leave out payloads and history; use [`get_message`](Self::get_message)
+    /// for those. Pending messages past their time-to-live are expired first, so they
+    /// are listed as expired.
+    pub fn list(&self, filter: &ListFilter) -> Result<Vec<MessageSummary>, Error> {
+        self.sweep()?;
+        // Only the filters that are set go in the WHERE clause, so SQLite can use
+        // the rowid and indexes for them
+        let mut sql = String::from(
+            "SELECT id, state, attempt_count, priority, created_at, available_at, completed_at
+             FROM messages WHERE queue = ?",
+        );
+        let mut values: Vec<Value> = vec![self.name.clone().into()];
+        let conditions = [
+            (
+                "state = ?",
+                filter.state.map(|state| state.as_str().to_string().into()),
+            ),
+            ("id > ?", filter.after.map(Value::from)),
+            ("id >= ?", filter.min_id.map(Value::from)),
+            ("id <= ?", filter.max_id.map(Value::from)),
+            (
+                "created_at >= ?",
+                filter
+                    .created_after
+                    .map(|time| system_time_millis(time).into()),
+            ),
+            (
+                "created_at < ?",
+                filter
+                    .created_before
+                    .map(|time| system_time_millis(time).into()),
+            ),
+        ];
+        for (condition, value) in conditions {
+            if let Some(value) = value {
+                sql.push_str(" AND ");
+                sql.push_str(condition);
+                values.push(value);
+            }
+        }
+        sql.push_str(" ORDER BY id LIMIT ?");
+        values.push(i64::try_from(filter.limit).unwrap_or(i64::MAX).into());
+
+        let mut statement = self.db.prepare_cached(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), |row| {
+            Ok(MessageSummary {
+                id: row.get(0)?,
+                state: row.get(1)?,
+                attempt_count: row.get(2)?,
+                priority: row.get(3)?,
+                created_at: millis_system_time(row.get(4)?),
+                available_at: millis_system_time(row.get(5)?),
+                completed_at: row.get::<_, Option<i64>>(6)?.map(millis_system_time),
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
     /// Returns the IDs of all messages in the dead letter queue.
+    ///
+    /// Pending messages past their time-to-live are expired first, as in [`size`](Self::size).
     pub fn dead_letters(&self) -> Result<Vec<i64>, Error> {
+        self.sweep()?;
         let mut statement = self
             .db
-            .prepare_cached("SELECT id FROM messages WHERE state = 'DEAD'")?;
-        let rows = statement.query_map([], |row| row.get(0))?;
-        rows.collect()
+            .prepare_cached("SELECT id FROM messages WHERE queue = ? AND state = 'DEAD'")?;
+        let rows = statement.query_map(params![self.name], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
     }
 
     /// Requeues dead letter messages back to pending state, resetting their attempt counts.
+    ///
+    /// Either every message is requeued or none are. Returns [`Error::NotFound`] if a
+    /// message does not exist, or [`Error::InvalidTransition`] if it is not dead.
     pub fn requeue_dead_letters(&mut self, ids: &[i64]) -> Result<(), Error> {
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for &id in ids {
+            let state = message_state(&tx, &self.name, id)?;
+            if state != MessageState::Dead {
+                return Err(Error::InvalidTransition {
+                    from: state,
+                    to: MessageState::Pending,
+                });
+            }
+        }
+
         let placeholders: String = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
         let sql = format!(
-            "UPDATE messages SET state = 'PENDING', attempt_count = 0 WHERE id IN ({}) AND state = 'DEAD'",
+            "UPDATE messages SET state = 'PENDING', attempt_count = 0
+             WHERE id IN ({}) AND queue = ? AND state = 'DEAD'",
             placeholders
         );
-        self.db.execute(&sql, rusqlite::params_from_iter(ids))?;
+        let mut values: Vec<&dyn rusqlite::ToSql> = ids.iter().map(|id| id as _).collect();
+        values.push(&self.name);
+        tx.execute(&sql, values.as_slice())?;
+        tx.commit()?;
+        self.notifier.notify();
         Ok(())
     }
 }
 
+const DEFAULT_QUEUE_NAME: &str = "default";
+
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+/// Selects the columns read by [`message_from_row`], to be followed by a WHERE clause.
+const MESSAGE_SELECT: &str =
+    "SELECT m.id, m.queue, m.state, p.data, m.attempt_count, m.priority, m.created_at,
+            m.available_at, m.reserved_at, m.reserved_until, m.completed_at, m.expires_at
+     FROM messages m JOIN payloads p ON m.payload_id = p.id";
+
+/// Columns added to `messages` after the initial schema, as `(name, definition)`.
+const MESSAGE_COLUMNS: &[(&str, &str)] = &[
+    ("reserved_until", "INTEGER"),
+    ("available_at", "INTEGER NOT NULL DEFAULT 0"),
+    ("priority", "INTEGER NOT NULL DEFAULT 0"),
+    ("queue", "TEXT NOT NULL DEFAULT 'default'"),
+    ("expires_at", "INTEGER"),
+    ("dedup_key", "TEXT"),
+    ("completed_at", "INTEGER"),
+    ("reservation_token", "TEXT"),
+    ("created_at", "INTEGER NOT NULL DEFAULT 0"),
+    ("reserved_at", "INTEGER"),
+    ("group_id", "INTEGER"),
+];
+
+/// Columns added to `payloads` after the initial schema, as `(name, definition)`.
+const PAYLOAD_COLUMNS: &[(&str, &str)] = &[("hash", "TEXT")];
+
+/// Per-message settings applied when a message is enqueued.
+#[derive(Default)]
+struct NewMessage {
+    /// Earliest time the message may be reserved, in milliseconds since the Unix epoch.
+    available_at: i64,
+    /// Higher priorities are reserved first.
+    priority: i64,
+    /// Overrides the queue's default time-to-live.
+    ttl: Option<Duration>,
+    /// Suppresses the message if one with the same key is in flight.
+    dedup_key: Option<String>,
+    /// Messages that must complete before this one can be reserved.
+    parents: Vec<i64>,
+    /// Group the message belongs to.
+    group_id: Option<i64>,
+}
+
+impl From<AddOptions> for NewMessage {
+    fn from(options: AddOptions) -> Self {
+        Self {
+            available_at: options.available_at,
+            priority: options.priority,
+            ttl: options.ttl,
+            dedup_key: options.key,
+            parents: options.parents,
+            group_id: None,
+        }
+    }
+}
+
+/// Returns the ID of the stored payload with `hash`, storing `payload` if there is none.
+///
+/// Identical payloads share one row, which is deleted along with the last message using it.
+fn store_payload(db: &Connection, payload: &[u8], hash: &str) -> rusqlite::Result<i64> {
+    let existing = db
+        .prepare_cached("SELECT id FROM payloads WHERE hash = ?")?
+        .query_row(params![hash], |row| row.get(0))
+        .optional()?;
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+    db.prepare_cached("INSERT INTO payloads (data, hash) VALUES (?, ?)")?
+        .execute(params![payload, hash])?;
+    Ok(db.last_insert_rowid())
+}
+
+/// Inserts a pending message for a stored payload, returning its ID.
+///
+/// `default_ttl` applies if the message does not set its own time-to-live.
+fn insert_message(
+    db: &Connection,
+    queue: &str,
+    payload_id: i64,
+    message: &NewMessage,
+    default_ttl: Option<Duration>,
+    now: i64,
+) -> rusqlite::Result<i64> {
+    let expires_at = message.ttl.or(default_ttl).map(|ttl| {
+        let available_at = message.available_at.max(now);
+        available_at.saturating_add(duration_millis(ttl))
+    });
+    db.prepare_cached(
+        "INSERT INTO messages
+         (queue, state, payload_id, created_at, available_at, priority, expires_at, dedup_key, group_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?
+    .execute(params![
+        queue,
+        MessageState::Pending.as_str(),
+        payload_id,
+        now,
+        message.available_at,
+        message.priority,
+        expires_at,
+        message.dedup_key,
+        message.group_id
+    ])?;
+    Ok(db.last_insert_rowid())
+}
+
+/// Returns expired reservations to pending, counting each as a failed attempt.
+///
+/// Clears the reservation token so the worker that abandoned the message can no longer finish it.
+/// Run inside a transaction, so the attempt and the message are updated together.
+/// Messages going back to pending are delayed by the retry policy, the same as a failure.
+fn reclaim_expired(
+    db: &Connection,
+    queue: &str,
+    max_attempts: Option<u32>,
+    retry_policy: Option<RetryPolicy>,
+    now: i64,
+) -> rusqlite::Result<usize> {
+    db.execute(
+        "UPDATE attempts SET ended_at = ?2, outcome = 'TIMED_OUT'
+         WHERE ended_at IS NULL AND message_id IN (
+             SELECT id FROM messages WHERE queue = ?1 AND state = 'RESERVED' AND reserved_until <= ?2
+         )",
+        params![queue, now],
+    )?;
+    let reclaimed = db
+        .prepare_cached(
+            "UPDATE messages
+             SET state = CASE WHEN ?1 IS NOT NULL AND attempt_count + 1 >= ?1 THEN 'DEAD' ELSE 'PENDING' END,
+                 attempt_count = attempt_count + 1,
+                 reserved_until = NULL,
+                 reservation_token = NULL
+             WHERE queue = ?3 AND state = 'RESERVED' AND reserved_until <= ?2
+             RETURNING id, state, attempt_count",
+        )?
+        .query_map(params![max_attempts, now, queue], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, MessageState>(1)?,
+                row.get::<_, u32>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(policy) = retry_policy {
+        for &(id, state, attempt_count) in &reclaimed {
+            if state == MessageState::Pending {
+                let retry_at = now.saturating_add(duration_millis(policy.delay(attempt_count)));
+                db.prepare_cached("UPDATE messages SET available_at = ? WHERE id = ?")?
+                    .execute(params![retry_at, id])?;
+            }
+        }
+    }
+    Ok(reclaimed.len())
+}
+
+/// Cancels pending messages with a parent that can no longer complete, if the policy says to.
+///
+/// Repeats until nothing changes, so cancellation reaches grandchildren too.
+fn cancel_blocked_children(
+    db: &Connection,
+    queue: &str,
+    policy: DeadParentPolicy,
+) -> rusqlite::Result<usize> {
+    if policy == DeadParentPolicy::LeaveBlocked {
+        return Ok(0);
+    }
+    let mut cancelled = 0;
+    loop {
+        let updated = db
+            .prepare_cached(
+                "UPDATE messages SET state = 'CANCELLED'
+                 WHERE queue = ? AND state = 'PENDING' AND EXISTS (
+                     SELECT 1 FROM dependencies d LEFT JOIN messages parent ON parent.id = d.parent_id
+                     WHERE d.message_id = messages.id
+                       AND (parent.state IS NULL OR parent.state IN ('DEAD', 'EXPIRED', 'CANCELLED'))
+                 )",
+            )?
+            .execute(params![queue])?;
+        if updated == 0 {
+            return Ok(cancelled);
+        }
+        cancelled += updated;
+    }
+}
+
+/// Marks groups with no pending or reserved members as finished and adds their callback messages.
+///
+/// Returns the number of callbacks added.
+fn finish_groups(db: &Connection, queue: &str, now: i64) -> rusqlite::Result<usize> {
+    let finished = db
+        .prepare_cached(
+            "UPDATE groups SET finished_at = ?2
+             WHERE queue = ?1 AND finished_at IS NULL AND NOT EXISTS (
+                 SELECT 1 FROM messages
+                 WHERE group_id = groups.id AND state IN ('PENDING', 'RESERVED')
+             )
+             RETURNING id, callback_queue",
+        )?
+        .query_map(params![queue, now], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (group_id, callback_queue) in &finished {
+        let (succeeded, died, _) = group_counts(db, *group_id)?;
+        let payload =
+            format!(r#"{{"group":{group_id},"succeeded":{succeeded},"died":{died}}}"#).into_bytes();
+        let payload_id = store_payload(db, &payload, &payload_hash(&payload))?;
+        let callback_id = insert_message(
+            db,
+            callback_queue,
+            payload_id,
+            &NewMessage::default(),
+            None,
+            now,
+        )?;
+        db.prepare_cached("UPDATE groups SET callback_id = ? WHERE id = ?")?
+            .execute(params![callback_id, group_id])?;
+    }
+    Ok(finished.len())
+}
+
+/// Counts the members of a group that succeeded, died, and remain, in that order.
+fn group_counts(db: &Connection, group_id: i64) -> rusqlite::Result<(usize, usize, usize)> {
+    db.prepare_cached(
+        "SELECT
+             COUNT(*) FILTER (WHERE state = 'COMPLETED'),
+             COUNT(*) FILTER (WHERE state IN ('DEAD', 'EXPIRED', 'CANCELLED')),
+             COUNT(*) FILTER (WHERE state IN ('PENDING', 'RESERVED'))
+         FROM messages WHERE group_id = ?",
+    )?
+    .query_row(params![group_id], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })
+}
+
+/// Closes the in-progress attempt of a message.
+fn end_attempt(
+    db: &Connection,
+    id: i64,
+    outcome: AttemptOutcome,
+    error: Option<&str>,
+    now: i64,
+) -> rusqlite::Result<usize> {
+    db.prepare_cached(
+        "UPDATE attempts SET ended_at = ?, outcome = ?, error = ?
+         WHERE message_id = ? AND ended_at IS NULL",
+    )?
+    .execute(params![now, outcome.as_str(), error, id])
+}
+
+/// Returns every attempt of a message, oldest first.
+fn attempt_history(db: &Connection, id: i64) -> rusqlite::Result<Vec<Attempt>> {
+    let mut statement = db.prepare_cached(
+        "SELECT attempt, started_at, ended_at, outcome, error FROM attempts
+         WHERE message_id = ?
+         ORDER BY attempt",
+    )?;
+    let rows = statement.query_map(params![id], |row| {
+        Ok(Attempt {
+            number: row.get(0)?,
+            started_at: millis_system_time(row.get(1)?),
+            ended_at: row.get::<_, Option<i64>>(2)?.map(millis_system_time),
+            outcome: row.get(3)?,
+            error: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Deletes completed messages no longer kept by the retention policy.
+fn apply_retention(
+    db: &Connection,
+    queue: &str,
+    policy: Option<RetentionPolicy>,
+    now: i64,
+) -> rusqlite::Result<usize> {
+    match policy {
+        None => Ok(0),
+        Some(RetentionPolicy::MaxAge(age)) => db.execute(
+            "DELETE FROM messages
+             WHERE queue = ? AND state = 'COMPLETED' AND completed_at <= ?
+               AND (group_id IS NULL OR group_id IN (SELECT id FROM groups WHERE finished_at IS NOT NULL))",
+            params![queue, now.saturating_sub(duration_millis(age))],
+        ),
+        Some(RetentionPolicy::KeepLast(count)) => db.execute(
+            "DELETE FROM messages
+             WHERE queue = ?1 AND state = 'COMPLETED' AND id NOT IN (
+                 SELECT id FROM messages WHERE queue = ?1 AND state = 'COMPLETED'
+                 ORDER BY completed_at DESC, id DESC
+                 LIMIT ?2
+             )
+               AND (group_id IS NULL OR group_id IN (SELECT id FROM groups WHERE finished_at IS NOT NULL))",
+            params![queue, i64::try_from(count).unwrap_or(i64::MAX)],
+        ),
+    }
+}
+
+/// Returns the current state of a message, or [`Error::NotFound`] if it is not in the queue.
+fn message_state(db: &Connection, queue: &str, id: i64) -> Result<MessageState, Error> {
+    db.query_row(
+        "SELECT state FROM messages WHERE id = ? AND queue = ?",
+        params![id, queue],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or(Error::NotFound(id))
+}
+
+/// Returns the current state of a message held by `token`.
+///
+/// Returns [`Error::NotFound`] if the message is not in the queue, or
+/// [`Error::StaleReservation`] if `token` is not its latest reservation.
+/// Messages that were never reserved return their state whatever the token.
+fn owned_state(db: &Connection, queue: &str, id: i64, token: &str) -> Result<MessageState, Error> {
+    let (state, current_token, reserved_at): (MessageState, Option<String>, Option<i64>) = db
+        .query_row(
+            "SELECT state, reservation_token, reserved_at FROM messages WHERE id = ? AND queue = ?",
+            params![id, queue],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?
+        .ok_or(Error::NotFound(id))?;
+    // A message that was never reserved has no token to be stale against, so callers
+    // report it as an invalid transition instead
+    let never_reserved = current_token.is_none() && reserved_at.is_none();
+    if never_reserved && state != MessageState::Reserved {
+        return Ok(state);
+    }
+    if current_token.as_deref() != Some(token) {
+        return Err(Error::StaleReservation(id));
+    }
+    Ok(state)
+}
+
+/// Expires pending messages whose time-to-live has passed, deleting them if `drop` is set.
+///
+/// Children of expired messages are cancelled according to `policy` before anything is deleted.
+fn expire_pending(
+    db: &Connection,
+    queue: &str,
+    drop: bool,
+    policy: DeadParentPolicy,
+    now: i64,
+) -> rusqlite::Result<usize> {
+    let expired = db.execute(
+        "UPDATE messages SET state = 'EXPIRED'
+         WHERE queue = ?1 AND state = 'PENDING' AND expires_at <= ?2",
+        params![queue, now],
+    )?;
+    if drop && expired > 0 {
+        cancel_blocked_children(db, queue, policy)?;
+        db.execute(
+            "DELETE FROM messages WHERE queue = ?1 AND state = 'EXPIRED' AND expires_at <= ?2",
+            params![queue, now],
+        )?;
+    }
+    Ok(expired)
+}
+
+/// Reads a [`Message`] from a row selected by [`MESSAGE_SELECT`], without its history.
+fn message_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        queue: row.get(1)?,
+        state: row.get(2)?,
+        payload: row.get(3)?,
+        attempt_count: row.get(4)?,
+        priority: row.get(5)?,
+        created_at: millis_system_time(row.get(6)?),
+        available_at: millis_system_time(row.get(7)?),
+        reserved_at: row.get::<_, Option<i64>>(8)?.map(millis_system_time),
+        reserved_until: row.get::<_, Option<i64>>(9)?.map(millis_system_time),
+        completed_at: row.get::<_, Option<i64>>(10)?.map(millis_system_time),
+        expires_at: row.get::<_, Option<i64>>(11)?.map(millis_system_time),
+        history: Vec::new(),
+    })
+}
+
+/// Returns the size of the database in bytes.
+fn database_size(db: &Connection) -> rusqlite::Result<u64> {
+    db.query_row(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn payload_hash(payload: &[u8]) -> String {
+    let digest = Sha256::digest(payload);
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256:{}", hex)
+}
+
+fn now_millis() -> i64 {
+    system_time_millis(SystemTime::now())
+}
+
+fn system_time_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(duration_millis)
+        .unwrap_or(0)
+}
+
+fn millis_system_time(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(u64::try_from(millis).unwrap_or(0))
+}
+
+fn duration_millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,7 +1,8 @@
 mod cli;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use cli::commands;
+use qoxide::MessageState;
 
 #[derive(Parser)]
 #[command(name = "qoxide")]
@@ -10,6 +11,9 @@ struct Cli {
     #[arg(short, long, default_value = "./qoxide.db", help = "Database path")]
     db: String,
 
+    #[arg(short, long, default_value = "default", help = "Queue name")]
+    queue: String,
+
     #[arg(long, help = "Output in JSON format")]
     json: bool,
 
@@ -26,24 +30,51 @@ enum Command {
 
         #[arg(long, help = "Treat payload as UTF-8 string instead of base64")]
         utf8: bool,
+
+        #[arg(long, help = "Delay in seconds before the message can be reserved")]
+        delay: Option<u64>,
+
+        #[arg(
+            long,
+            help = "Deduplication key; returns the existing ID if a message with this key is in flight"
+        )]
+        key: Option<String>,
     },
 
     #[command(about = "Reserve the next pending message")]
     Reserve {
         #[arg(long, help = "Output payload as UTF-8 string instead of base64")]
         utf8: bool,
+
+        #[arg(long, help = "Wait up to this many seconds for a message to arrive")]
+        wait: Option<u64>,
     },
 
     #[command(about = "Mark a message as completed")]
     Complete {
         #[arg(help = "Message ID")]
         id: i64,
+
+        #[arg(help = "Reservation token returned by reserve")]
+        token: String,
     },
 
     #[command(about = "Mark a message as failed")]
     Fail {
         #[arg(help = "Message ID")]
         id: i64,
+
+        #[arg(help = "Reservation token returned by reserve")]
+        token: String,
+
+        #[arg(
+            long,
+            help = "Wait this many seconds before retrying, instead of the retry policy"
+        )]
+        delay: Option<u64>,
+
+        #[arg(long, help = "Why the attempt failed, shown by history")]
+        reason: Option<String>,
     },
 
     #[command(about = "Remove a message permanently")]
@@ -61,9 +92,56 @@ enum Command {
         utf8: bool,
     },
 
+    #[command(about = "Show a message's state, attempts, and timestamps")]
+    Inspect {
+        #[arg(help = "Message ID")]
+        id: i64,
+
+        #[arg(long, help = "Output payload as UTF-8 string instead of base64")]
+        utf8: bool,
+    },
+
+    #[command(about = "Show every attempt of a message and how it ended")]
+    History {
+        #[arg(help = "Message ID")]
+        id: i64,
+    },
+
     #[command(about = "Show queue statistics")]
     Size,
 
+    #[command(about = "List messages oldest first, one page at a time")]
+    List {
+        #[arg(long, value_enum, help = "Only list messages in this state")]
+        state: Option<StateArg>,
+
+        #[arg(
+            long,
+            default_value_t = 100,
+            help = "Maximum number of messages to list"
+        )]
+        limit: usize,
+
+        #[arg(long, help = "List messages after this ID, to fetch the next page")]
+        after: Option<i64>,
+    },
+
+    #[command(about = "Delete every message in a state older than a given age")]
+    Purge {
+        #[arg(long, value_enum, help = "State of the messages to delete")]
+        state: StateArg,
+
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Only delete messages older than this many seconds"
+        )]
+        older_than: u64,
+    },
+
+    #[command(about = "Reclaim unused space in the database file and print the bytes freed")]
+    Compact,
+
     #[command(about = "List dead letter message IDs", name = "dead-letters")]
     DeadLetters,
 
@@ -74,36 +152,111 @@ enum Command {
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum StateArg {
+    Pending,
+    Reserved,
+    Completed,
+    Dead,
+    Expired,
+    Cancelled,
+}
+
+impl From<StateArg> for MessageState {
+    fn from(state: StateArg) -> Self {
+        match state {
+            StateArg::Pending => MessageState::Pending,
+            StateArg::Reserved => MessageState::Reserved,
+            StateArg::Completed => MessageState::Completed,
+            StateArg::Dead => MessageState::Dead,
+            StateArg::Expired => MessageState::Expired,
+            StateArg::Cancelled => MessageState::Cancelled,
+        }
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Command::Add { payload, utf8 } => {
-            commands::add(&cli.db, &payload, utf8, cli.json);
+        Command::Add {
+            payload,
+            utf8,
+            delay,
+            key,
+        } => {
+            commands::add(
+                &cli.db,
+                &cli.queue,
+                &payload,
+                utf8,
+                delay,
+                key.as_deref(),
+                cli.json,
+            );
         }
-        Command::Reserve { utf8 } => {
-            commands::reserve(&cli.db, utf8, cli.json);
+        Command::Reserve { utf8, wait } => {
+            commands::reserve(&cli.db, &cli.queue, utf8, wait, cli.json);
         }
-        Command::Complete { id } => {
-            commands::complete(&cli.db, id, cli.json);
+        Command::Complete { id, token } => {
+            commands::complete(&cli.db, &cli.queue, id, &token, cli.json);
         }
-        Command::Fail { id } => {
-            commands::fail(&cli.db, id, cli.json);
+        Command::Fail {
+            id,
+            token,
+            delay,
+            reason,
+        } => {
+            commands::fail(
+                &cli.db,
+                &cli.queue,
+                id,
+                &token,
+                delay,
+                reason.as_deref(),
+                cli.json,
+            );
         }
         Command::Remove { id } => {
-            commands::remove(&cli.db, id, cli.json);
+            commands::remove(&cli.db, &cli.queue, id, cli.json);
         }
         Command::Get { id, utf8 } => {
-            commands::get(&cli.db, id, utf8, cli.json);
+            commands::get(&cli.db, &cli.queue, id, utf8, cli.json);
+        }
+        Command::Inspect { id, utf8 } => {
+            commands::inspect(&cli.db, &cli.queue, id, utf8, cli.json);
+        }
+        Command::History { id } => {
+            commands::history(&cli.db, &cli.queue, id, cli.json);
         }
         Command::Size => {
-            commands::show_size(&cli.db, cli.json);
+            commands::show_size(&cli.db, &cli.queue, cli.json);
+        }
+        Command::List {
+            state,
+            limit,
+            after,
+        } => {
+            commands::list(
+                &cli.db,
+                &cli.queue,
+                state.map(MessageState::from),
+                limit,
+                after,
+                cli.json,
+            );
+        }
+        Command::Purge { state, older_than } => {
+            commands::purge(&cli.db, &cli.queue, state.into(), older_than, cli.json);
+        }
+        Command::Compact => {
+            commands::compact(&cli.db, &cli.queue, cli.json);
         }
         Command::DeadLetters => {
-            commands::list_dead_letters(&cli.db, cli.json);
+            commands::list_dead_letters(&cli.db, &cli.queue, cli.json);
         }
         Command::Requeue { ids } => {
-            commands::requeue_dead_letters(&cli.db, &ids, cli.json);
+            commands::requeue_dead_letters(&cli.db, &cli.queue, &ids, cli.json);
         }
     }
 }
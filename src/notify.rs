@@ -0,0 +1,87 @@
+use crate::Error;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+/// How often a waiting reserve re-checks the database, to catch messages added
+/// by other processes and delayed messages becoming available.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wakes up threads waiting for messages when a message becomes pending in this process.
+///
+/// Queues opened on the same file share a notifier, so an `add` on one handle
+/// wakes a `reserve_wait` on another.
+pub(crate) struct Notifier {
+    generation: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Notifier {
+    fn new() -> Self {
+        Self {
+            generation: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Returns the notifier shared by every queue opened on `path` in this process.
+    pub(crate) fn for_path(path: &str) -> Arc<Self> {
+        if path == ":memory:" {
+            return Arc::new(Self::new());
+        }
+
+        static NOTIFIERS: OnceLock<Mutex<HashMap<String, Weak<Notifier>>>> = OnceLock::new();
+        let key = std::fs::canonicalize(path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        let mut notifiers = NOTIFIERS.get_or_init(Default::default).lock().unwrap();
+        notifiers.retain(|_, notifier| notifier.strong_count() > 0);
+        if let Some(notifier) = notifiers.get(&key).and_then(Weak::upgrade) {
+            return notifier;
+        }
+        let notifier = Arc::new(Self::new());
+        notifiers.insert(key, Arc::downgrade(&notifier));
+        notifier
+    }
+
+    /// Wakes every thread waiting on this notifier.
+    pub(crate) fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    /// Calls `attempt` until it finds a message or `timeout` passes.
+    ///
+    /// Between attempts, parks until notified or the poll interval passes.
+    /// Returns [`Error::Empty`] if the timeout passes first.
+    pub(crate) fn wait_for<T>(
+        &self,
+        timeout: Duration,
+        mut attempt: impl FnMut() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let seen = *self.generation.lock().unwrap();
+            match attempt() {
+                Err(Error::Empty) => {}
+                result => return result,
+            }
+
+            let remaining = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => POLL_INTERVAL,
+            };
+            if remaining.is_zero() {
+                return Err(Error::Empty);
+            }
+
+            let generation = self.generation.lock().unwrap();
+            let _ = self
+                .changed
+                .wait_timeout_while(generation, remaining.min(POLL_INTERVAL), |generation| {
+                    *generation == seen
+                })
+                .unwrap();
+        }
+    }
+}
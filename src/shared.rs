@@ -0,0 +1,320 @@
+use crate::Error;
+use crate::notify::Notifier;
+use crate::{
+    AddOptions, FailOptions, Group, GroupSummary, ListFilter, Message, MessageState,
+    MessageSummary, QoxideQueue, QoxideQueueBuilder, QueueSize, Reservation,
+};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_POOL_SIZE: usize = 4;
+
+/// A thread-safe handle to a queue, backed by a pool of connections to the same database.
+///
+/// Cloning the handle is cheap and every clone shares the same pool, so it can be
+/// handed to as many threads as needed. Each operation checks out a connection for
+/// its duration, giving the same atomicity guarantees as [`QoxideQueue`].
+///
+/// # Example
+///
+/// ```
+/// use qoxide::QoxideQueue;
+/// use std::thread;
+///
+/// # fn main() -> Result<(), qoxide::Error> {
+/// let queue = QoxideQueue::builder().pool_size(4).build_shared()?;
+///
+/// let producer = queue.clone();
+/// thread::spawn(move || producer.add(b"job".to_vec()))
+///     .join()
+///     .unwrap()?;
+///
+/// let reservation = queue.reserve()?;
+/// queue.complete(reservation.id, &reservation.token)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SharedQoxideQueue {
+    pool: Arc<Pool>,
+}
+
+struct Pool {
+    builder: QoxideQueueBuilder,
+    name: String,
+    notifier: Arc<Notifier>,
+    max_size: usize,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<QoxideQueue>,
+    open: usize,
+}
+
+/// Returns a checked out queue to its pool when dropped, even if the caller panics.
+struct Checkout<'a> {
+    pool: &'a Pool,
+    queue: Option<QoxideQueue>,
+}
+
+impl Drop for Checkout<'_> {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            self.pool.state.lock().unwrap().idle.push(queue);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+impl Pool {
+    fn checkout(&self) -> Result<Checkout<'_>, Error> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(queue) = state.idle.pop() {
+                return Ok(Checkout {
+                    pool: self,
+                    queue: Some(queue),
+                });
+            }
+            if state.open < self.max_size {
+                state.open += 1;
+                drop(state);
+                return match self.builder.clone().build() {
+                    Ok(queue) => Ok(Checkout {
+                        pool: self,
+                        queue: Some(queue),
+                    }),
+                    Err(err) => {
+                        self.state.lock().unwrap().open -= 1;
+                        self.returned.notify_one();
+                        Err(err)
+                    }
+                };
+            }
+            state = self.returned.wait(state).unwrap();
+        }
+    }
+}
+
+impl SharedQoxideQueue {
+    pub(crate) fn new(builder: QoxideQueueBuilder) -> Result<Self, Error> {
+        let in_memory = builder
+            .path
+            .as_deref()
+            .is_none_or(|path| path == ":memory:");
+        let max_size = if in_memory {
+            1
+        } else {
+            builder.pool_size.unwrap_or(DEFAULT_POOL_SIZE)
+        };
+
+        // Open the first connection eagerly so configuration errors surface here
+        let queue = builder.clone().build()?;
+        let name = queue.name().to_string();
+        let notifier = Arc::clone(&queue.notifier);
+        Ok(Self {
+            pool: Arc::new(Pool {
+                builder,
+                name,
+                notifier,
+                max_size,
+                state: Mutex::new(PoolState {
+                    idle: vec![queue],
+                    open: 1,
+                }),
+                returned: Condvar::new(),
+            }),
+        })
+    }
+
+    /// Runs `f` with exclusive access to one of the pooled queues.
+    ///
+    /// Blocks until a connection is available. Use this to run several operations
+    /// on the same connection, or to reach methods not exposed on the shared handle.
+    pub fn with<T>(
+        &self,
+        f: impl FnOnce(&mut QoxideQueue) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut checkout = self.pool.checkout()?;
+        f(checkout.queue.as_mut().expect("checked out queue"))
+    }
+
+    /// Returns the name of the queue within the database.
+    pub fn name(&self) -> &str {
+        &self.pool.name
+    }
+
+    /// See [`QoxideQueue::size`].
+    pub fn size(&self) -> Result<QueueSize, Error> {
+        self.with(|queue| queue.size())
+    }
+
+    /// See [`QoxideQueue::get`].
+    pub fn get(&self, id: i64) -> Result<Vec<u8>, Error> {
+        self.with(|queue| queue.get(id))
+    }
+
+    /// See [`QoxideQueue::get_message`].
+    pub fn get_message(&self, id: i64) -> Result<Message, Error> {
+        self.with(|queue| queue.get_message(id))
+    }
+
+    /// See [`QoxideQueue::add`].
+    pub fn add(&self, payload: Vec<u8>) -> Result<i64, Error> {
+        self.with(|queue| queue.add(payload))
+    }
+
+    /// See [`QoxideQueue::add_with`].
+    pub fn add_with(&self, payload: Vec<u8>, options: AddOptions) -> Result<i64, Error> {
+        self.with(|queue| queue.add_with(payload, options))
+    }
+
+    /// See [`QoxideQueue::add_delayed`].
+    pub fn add_delayed(&self, payload: Vec<u8>, delay: Duration) -> Result<i64, Error> {
+        self.with(|queue| queue.add_delayed(payload, delay))
+    }
+
+    /// See [`QoxideQueue::add_at`].
+    pub fn add_at(&self, payload: Vec<u8>, time: SystemTime) -> Result<i64, Error> {
+        self.with(|queue| queue.add_at(payload, time))
+    }
+
+    /// See [`QoxideQueue::add_with_priority`].
+    pub fn add_with_priority(&self, payload: Vec<u8>, priority: i64) -> Result<i64, Error> {
+        self.with(|queue| queue.add_with_priority(payload, priority))
+    }
+
+    /// See [`QoxideQueue::add_with_ttl`].
+    pub fn add_with_ttl(&self, payload: Vec<u8>, ttl: Duration) -> Result<i64, Error> {
+        self.with(|queue| queue.add_with_ttl(payload, ttl))
+    }
+
+    /// See [`QoxideQueue::add_unique`].
+    pub fn add_unique(&self, key: &str, payload: Vec<u8>) -> Result<i64, Error> {
+        self.with(|queue| queue.add_unique(key, payload))
+    }
+
+    /// See [`QoxideQueue::add_after`].
+    pub fn add_after(&self, payload: Vec<u8>, parents: &[i64]) -> Result<i64, Error> {
+        self.with(|queue| queue.add_after(payload, parents))
+    }
+
+    /// See [`QoxideQueue::add_group`].
+    pub fn add_group(&self, payloads: Vec<Vec<u8>>, callback_queue: &str) -> Result<Group, Error> {
+        self.with(|queue| queue.add_group(payloads, callback_queue))
+    }
+
+    /// See [`QoxideQueue::group`].
+    pub fn group(&self, id: i64) -> Result<GroupSummary, Error> {
+        self.with(|queue| queue.group(id))
+    }
+
+    /// See [`QoxideQueue::add_batch`].
+    pub fn add_batch(&self, payloads: Vec<Vec<u8>>) -> Result<Vec<i64>, Error> {
+        self.with(|queue| queue.add_batch(payloads))
+    }
+
+    /// See [`QoxideQueue::fan_out`].
+    pub fn fan_out(&self, payload: Vec<u8>, n: usize) -> Result<Vec<i64>, Error> {
+        self.with(|queue| queue.fan_out(payload, n))
+    }
+
+    /// See [`QoxideQueue::reserve`].
+    pub fn reserve(&self) -> Result<Reservation, Error> {
+        self.with(|queue| queue.reserve())
+    }
+
+    /// See [`QoxideQueue::reserve_wait`].
+    ///
+    /// No connection is held while waiting, so other threads can keep using the pool.
+    pub fn reserve_wait(&self, timeout: Duration) -> Result<Reservation, Error> {
+        self.pool.notifier.wait_for(timeout, || self.reserve())
+    }
+
+    /// See [`QoxideQueue::reserve_batch`].
+    pub fn reserve_batch(&self, n: usize) -> Result<Vec<Reservation>, Error> {
+        self.with(|queue| queue.reserve_batch(n))
+    }
+
+    /// See [`QoxideQueue::complete`].
+    pub fn complete(&self, id: i64, token: &str) -> Result<(), Error> {
+        self.with(|queue| queue.complete(id, token))
+    }
+
+    /// See [`QoxideQueue::extend`].
+    pub fn extend(&self, id: i64, token: &str, duration: Duration) -> Result<(), Error> {
+        self.with(|queue| queue.extend(id, token, duration))
+    }
+
+    /// See [`QoxideQueue::heartbeat`].
+    pub fn heartbeat(&self, id: i64, token: &str) -> Result<(), Error> {
+        self.with(|queue| queue.heartbeat(id, token))
+    }
+
+    /// See [`QoxideQueue::fail`].
+    pub fn fail(&self, id: i64, token: &str) -> Result<MessageState, Error> {
+        self.with(|queue| queue.fail(id, token))
+    }
+
+    /// See [`QoxideQueue::fail_with_reason`].
+    pub fn fail_with_reason(
+        &self,
+        id: i64,
+        token: &str,
+        reason: &str,
+    ) -> Result<MessageState, Error> {
+        self.with(|queue| queue.fail_with_reason(id, token, reason))
+    }
+
+    /// See [`QoxideQueue::fail_with`].
+    pub fn fail_with(
+        &self,
+        id: i64,
+        token: &str,
+        options: FailOptions,
+    ) -> Result<MessageState, Error> {
+        self.with(|queue| queue.fail_with(id, token, options))
+    }
+
+    /// See [`QoxideQueue::fail_with_delay`].
+    pub fn fail_with_delay(
+        &self,
+        id: i64,
+        token: &str,
+        delay: Duration,
+    ) -> Result<MessageState, Error> {
+        self.with(|queue| queue.fail_with_delay(id, token, delay))
+    }
+
+    /// See [`QoxideQueue::remove`].
+    pub fn remove(&self, id: i64) -> Result<(), Error> {
+        self.with(|queue| queue.remove(id))
+    }
+
+    /// See [`QoxideQueue::purge`].
+    pub fn purge(&self, state: MessageState, older_than: Duration) -> Result<usize, Error> {
+        self.with(|queue| queue.purge(state, older_than))
+    }
+
+    /// See [`QoxideQueue::compact`].
+    pub fn compact(&self) -> Result<u64, Error> {
+        self.with(|queue| queue.compact())
+    }
+
+    /// See [`QoxideQueue::list`].
+    pub fn list(&self, filter: &ListFilter) -> Result<Vec<MessageSummary>, Error> {
+        self.with(|queue| queue.list(filter))
+    }
+
+    /// See [`QoxideQueue::dead_letters`].
+    pub fn dead_letters(&self) -> Result<Vec<i64>, Error> {
+        self.with(|queue| queue.dead_letters())
+    }
+
+    /// See [`QoxideQueue::requeue_dead_letters`].
+    pub fn requeue_dead_letters(&self, ids: &[i64]) -> Result<(), Error> {
+        self.with(|queue| queue.requeue_dead_letters(ids))
+    }
+}
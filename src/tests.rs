@@ -1,9 +1,28 @@
 use super::*;
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use super::*;
 
+    /// A database file in the system temp directory, removed on drop.
+    struct TempDb(String);
+
+    impl TempDb {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("qoxide-{}.db", uuid::Uuid::new_v4()));
+            Self(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.0, suffix));
+            }
+        }
+    }
+
     #[test]
     fn test_queue_size() {
         let mut queue = QoxideQueue::new();
@@ -37,11 +56,14 @@ mod tests {
         let payload = b"test".to_vec();
         let id = queue.add(payload.clone()).expect("Failed to add message");
 
-        let payload = queue.reserve().expect("Message should be found");
-        assert_eq!(payload, payload);
+        let reservation = queue.reserve().expect("Message should be found");
+        assert_eq!(reservation.id, id);
+        assert_eq!(reservation.payload, payload);
         assert_eq!(queue.size().unwrap().pending, 0);
 
-        queue.fail(id).expect("Failed to fail message");
+        queue
+            .fail(id, &reservation.token)
+            .expect("Failed to fail message");
         assert_eq!(queue.size().unwrap().pending, 1);
 
         queue.reserve().expect("Message should be found");
@@ -68,20 +90,21 @@ mod tests {
         let payload = b"test".to_vec();
         queue.add(payload.clone()).expect("Failed to add message");
 
-        let (id, _) = queue.reserve().expect("Message should be found");
+        let reservation = queue.reserve().expect("Message should be found");
+        let id = reservation.id;
 
         // First two failures should return to pending (attempts 1 and 2)
-        let state = queue.fail(id).unwrap();
+        let state = queue.fail(id, &reservation.token).unwrap();
         assert_eq!(state, MessageState::Pending);
         assert_eq!(queue.size().unwrap().pending, 1);
 
-        queue.reserve().unwrap();
-        let state = queue.fail(id).unwrap();
+        let reservation = queue.reserve().unwrap();
+        let state = queue.fail(id, &reservation.token).unwrap();
         assert_eq!(state, MessageState::Pending);
 
         // Third failure should move to DLQ (attempt 3 = max_attempts)
-        queue.reserve().unwrap();
-        let state = queue.fail(id).unwrap();
+        let reservation = queue.reserve().unwrap();
+        let state = queue.fail(id, &reservation.token).unwrap();
         assert_eq!(state, MessageState::Dead);
 
         let sizes = queue.size().unwrap();
@@ -96,10 +119,10 @@ mod tests {
         let payload = b"dead message".to_vec();
         queue.add(payload.clone()).expect("Failed to add message");
 
-        let (id, _) = queue.reserve().unwrap();
+        let reservation = queue.reserve().unwrap();
 
         // First failure moves to DLQ (max_attempts = 1)
-        queue.fail(id).unwrap();
+        queue.fail(reservation.id, &reservation.token).unwrap();
 
         let dead = queue.dead_letters().unwrap();
         assert_eq!(dead.len(), 1);
@@ -112,10 +135,10 @@ mod tests {
         let id1 = queue.add(b"test1".to_vec()).unwrap();
         let id2 = queue.add(b"test2".to_vec()).unwrap();
 
-        queue.reserve().unwrap();
-        queue.reserve().unwrap();
-        queue.fail(id1).unwrap();
-        queue.fail(id2).unwrap();
+        let first = queue.reserve().unwrap();
+        let second = queue.reserve().unwrap();
+        queue.fail(id1, &first.token).unwrap();
+        queue.fail(id2, &second.token).unwrap();
 
         assert_eq!(queue.size().unwrap().dead, 2);
 
@@ -137,4 +160,1150 @@ mod tests {
 
         assert_eq!(queue.size().unwrap().total, 0);
     }
+
+    #[test]
+    fn test_expired_reservation_is_reclaimed() {
+        let mut queue = QoxideQueue::builder()
+            .visibility_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        queue.reserve().unwrap();
+        assert!(queue.reserve().is_err());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        let reservation = queue
+            .reserve()
+            .expect("Expired reservation should be reclaimed");
+        assert_eq!(reservation.id, id);
+        assert_eq!(reservation.payload, b"test".to_vec());
+    }
+
+    #[test]
+    fn test_expired_reservation_counts_as_attempt() {
+        let mut queue = QoxideQueue::builder()
+            .max_attempts(1)
+            .visibility_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        queue.add(b"test".to_vec()).unwrap();
+
+        queue.reserve().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        // The abandoned reservation was the only allowed attempt
+        assert!(queue.reserve().is_err());
+        let sizes = queue.size().unwrap();
+        assert_eq!(sizes.reserved, 0);
+        assert_eq!(sizes.dead, 1);
+    }
+
+    #[test]
+    fn test_delayed_message_is_not_reserved_early() {
+        let mut queue = QoxideQueue::new();
+        let id = queue
+            .add_delayed(b"later".to_vec(), Duration::from_millis(20))
+            .unwrap();
+
+        assert!(queue.reserve().is_err());
+        assert_eq!(queue.size().unwrap().pending, 1);
+
+        std::thread::sleep(Duration::from_millis(30));
+
+        let reservation = queue.reserve().expect("Delay should have passed");
+        assert_eq!(reservation.id, id);
+    }
+
+    #[test]
+    fn test_add_at() {
+        let mut queue = QoxideQueue::new();
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        queue.add_at(b"future".to_vec(), future).unwrap();
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        let id = queue.add_at(b"past".to_vec(), past).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(reservation.id, id);
+        assert_eq!(reservation.payload, b"past".to_vec());
+        assert!(queue.reserve().is_err());
+    }
+
+    #[test]
+    fn test_add_with_combines_options() {
+        let mut queue = QoxideQueue::new();
+        let delayed = queue
+            .add_with(
+                b"delayed".to_vec(),
+                AddOptions::new()
+                    .delay(Duration::from_secs(3600))
+                    .priority(9)
+                    .key("job"),
+            )
+            .unwrap();
+        let normal = queue.add(b"normal".to_vec()).unwrap();
+
+        assert_eq!(
+            queue
+                .add_with(b"again".to_vec(), AddOptions::new().key("job"))
+                .unwrap(),
+            delayed
+        );
+        assert_eq!(queue.get_message(delayed).unwrap().priority, 9);
+        assert_eq!(queue.reserve().unwrap().id, normal);
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+    }
+
+    #[test]
+    fn test_reserve_highest_priority_first() {
+        let mut queue = QoxideQueue::new();
+        let low = queue.add_with_priority(b"low".to_vec(), -1).unwrap();
+        let normal = queue.add(b"normal".to_vec()).unwrap();
+        let high_first = queue.add_with_priority(b"high".to_vec(), 5).unwrap();
+        let high_second = queue.add_with_priority(b"high".to_vec(), 5).unwrap();
+
+        let order: Vec<i64> = (0..4).map(|_| queue.reserve().unwrap().id).collect();
+        assert_eq!(order, vec![high_first, high_second, normal, low]);
+    }
+
+    #[test]
+    fn test_named_queues_are_isolated() {
+        let db = TempDb::new();
+        let mut emails = QoxideQueue::builder()
+            .path(&db.0)
+            .name("emails")
+            .max_attempts(1)
+            .build()
+            .unwrap();
+        let mut reports = QoxideQueue::builder()
+            .path(&db.0)
+            .name("reports")
+            .build()
+            .unwrap();
+
+        let email_id = emails.add(b"email".to_vec()).unwrap();
+        assert_eq!(emails.size().unwrap().total, 1);
+        assert_eq!(reports.size().unwrap().total, 0);
+        assert!(reports.reserve().is_err());
+        assert!(reports.get(email_id).is_err());
+
+        let reservation = emails.reserve().unwrap();
+        emails.fail(reservation.id, &reservation.token).unwrap();
+        assert_eq!(emails.dead_letters().unwrap(), vec![email_id]);
+        assert!(reports.dead_letters().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_message_expires_before_reservation() {
+        let mut queue = QoxideQueue::builder()
+            .ttl(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let stale = queue.add(b"stale".to_vec()).unwrap();
+        let fresh = queue
+            .add_with_ttl(b"fresh".to_vec(), Duration::from_secs(3600))
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(
+            queue.get_message(stale).unwrap().state,
+            MessageState::Expired
+        );
+
+        let sizes = queue.size().unwrap();
+        assert_eq!(sizes.pending, 1);
+        assert_eq!(sizes.expired, 1);
+        assert_eq!(queue.reserve().unwrap().id, fresh);
+        assert!(queue.reserve().is_err());
+    }
+
+    #[test]
+    fn test_reserved_message_does_not_expire() {
+        let mut queue = QoxideQueue::builder()
+            .ttl(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        queue.fail(id, &reservation.token).unwrap();
+
+        assert_eq!(queue.reserve().unwrap().id, id);
+        assert_eq!(queue.size().unwrap().expired, 0);
+    }
+
+    #[test]
+    fn test_drop_expired() {
+        let mut queue = QoxideQueue::builder().drop_expired(true).build().unwrap();
+        queue
+            .add_with_ttl(b"stale".to_vec(), Duration::from_millis(10))
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(queue.reserve().is_err());
+        let sizes = queue.size().unwrap();
+        assert_eq!(sizes.total, 0);
+        assert_eq!(sizes.expired, 0);
+    }
+
+    #[test]
+    fn test_add_batch() {
+        let mut queue = QoxideQueue::new();
+        let ids = queue
+            .add_batch(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+            .unwrap();
+
+        assert_eq!(ids.len(), 3);
+        assert_eq!(queue.size().unwrap().pending, 3);
+        assert_eq!(queue.get(ids[1]).unwrap(), b"b".to_vec());
+    }
+
+    #[test]
+    fn test_reserve_batch() {
+        let mut queue = QoxideQueue::new();
+        let ids = queue
+            .add_batch(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+            .unwrap();
+
+        let reserved = queue.reserve_batch(2).unwrap();
+        let reserved: Vec<_> = reserved
+            .into_iter()
+            .map(|reservation| (reservation.id, reservation.payload))
+            .collect();
+        assert_eq!(
+            reserved,
+            vec![(ids[0], b"a".to_vec()), (ids[1], b"b".to_vec())]
+        );
+        assert_eq!(queue.size().unwrap().reserved, 2);
+
+        assert_eq!(queue.reserve_batch(5).unwrap().len(), 1);
+        assert!(queue.reserve_batch(5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_add_unique_returns_existing_id() {
+        let mut queue = QoxideQueue::new();
+        let id = queue.add_unique("job-1", b"test".to_vec()).unwrap();
+        assert_eq!(queue.add_unique("job-1", b"test".to_vec()).unwrap(), id);
+        assert_eq!(queue.size().unwrap().total, 1);
+
+        // Still a duplicate while reserved
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(queue.add_unique("job-1", b"test".to_vec()).unwrap(), id);
+
+        // Completed messages only count within the dedup window
+        queue.complete(id, &reservation.token).unwrap();
+        let new_id = queue.add_unique("job-1", b"test".to_vec()).unwrap();
+        assert_ne!(new_id, id);
+    }
+
+    #[test]
+    fn test_deduplicate_payloads_with_window() {
+        let mut queue = QoxideQueue::builder()
+            .deduplicate_payloads(true)
+            .dedup_window(Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let ids = queue
+            .add_batch(vec![b"a".to_vec(), b"a".to_vec(), b"b".to_vec()])
+            .unwrap();
+        assert_eq!(ids[0], ids[1]);
+        assert_ne!(ids[0], ids[2]);
+
+        let reservation = queue.reserve().unwrap();
+        queue.complete(reservation.id, &reservation.token).unwrap();
+        assert_eq!(queue.add(b"a".to_vec()).unwrap(), reservation.id);
+        assert_eq!(queue.size().unwrap().total, 2);
+    }
+
+    #[test]
+    fn test_fan_out_and_groups_suppress_later_duplicates() {
+        let mut queue = QoxideQueue::builder()
+            .deduplicate_payloads(true)
+            .build()
+            .unwrap();
+        let ids = queue.fan_out(b"same".to_vec(), 2).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(queue.add(b"same".to_vec()).unwrap(), ids[1]);
+
+        let group = queue
+            .add_group(vec![b"member".to_vec(), b"member".to_vec()], "done")
+            .unwrap();
+        assert_eq!(group.messages.len(), 2);
+        assert_eq!(queue.add(b"member".to_vec()).unwrap(), group.messages[1]);
+        assert_eq!(queue.size().unwrap().total, 4);
+    }
+
+    #[test]
+    fn test_shared_queue_across_threads() {
+        let db = TempDb::new();
+        let queue = QoxideQueue::builder()
+            .path(&db.0)
+            .pool_size(4)
+            .build_shared()
+            .unwrap();
+
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        queue.add(b"test".to_vec()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(queue.size().unwrap().pending, 100);
+
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    let mut ids = Vec::new();
+                    while let Ok(reservation) = queue.reserve() {
+                        queue.complete(reservation.id, &reservation.token).unwrap();
+                        ids.push(reservation.id);
+                    }
+                    ids
+                })
+            })
+            .collect();
+        let mut ids: Vec<i64> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 100);
+        assert_eq!(queue.size().unwrap().completed, 100);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_queue() {
+        let queue = QoxideQueue::builder()
+            .max_attempts(1)
+            .build_async()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).await.unwrap();
+
+        let reservation = queue.reserve().await.unwrap();
+        assert_eq!(reservation.id, id);
+        assert_eq!(reservation.payload, b"test".to_vec());
+
+        assert_eq!(
+            queue.fail(id, &reservation.token).await.unwrap(),
+            MessageState::Dead
+        );
+        assert_eq!(queue.dead_letters().await.unwrap(), vec![id]);
+    }
+
+    #[test]
+    fn test_reserve_wait_times_out() {
+        let mut queue = QoxideQueue::new();
+        let started = std::time::Instant::now();
+
+        assert!(queue.reserve_wait(Duration::from_millis(50)).is_err());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_reserve_wait_wakes_on_add() {
+        let queue = QoxideQueue::builder().build_shared().unwrap();
+
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.reserve_wait(Duration::from_secs(10)))
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        let reservation = consumer.join().unwrap().expect("Consumer should be woken");
+        assert_eq!(reservation.id, id);
+    }
+
+    #[test]
+    fn test_error_variants() {
+        let mut queue = QoxideQueue::builder().max_attempts(3).build().unwrap();
+
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        assert!(matches!(queue.get(42), Err(Error::NotFound(42))));
+        assert!(matches!(queue.fail(42, "token"), Err(Error::NotFound(42))));
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        let result = QoxideQueue::builder().max_attempts(0).build();
+        assert!(matches!(result, Err(Error::Config(_))));
+
+        let result = QoxideQueue::builder().name("").build();
+        assert!(matches!(result, Err(Error::Config(_))));
+
+        let result = QoxideQueue::builder().pool_size(0).build_shared();
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_complete_requires_reservation() {
+        let mut queue = QoxideQueue::new();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        assert!(matches!(
+            queue.complete(id, "token"),
+            Err(Error::InvalidTransition {
+                from: MessageState::Pending,
+                to: MessageState::Completed
+            })
+        ));
+        assert!(matches!(
+            queue.complete(42, "token"),
+            Err(Error::NotFound(42))
+        ));
+
+        let reservation = queue.reserve().unwrap();
+        queue.complete(id, &reservation.token).unwrap();
+        assert!(matches!(
+            queue.complete(id, &reservation.token),
+            Err(Error::InvalidTransition {
+                from: MessageState::Completed,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_fail_requires_reservation() {
+        let mut queue = QoxideQueue::builder().max_attempts(1).build().unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        assert!(matches!(
+            queue.fail(id, "token"),
+            Err(Error::InvalidTransition {
+                from: MessageState::Pending,
+                ..
+            })
+        ));
+
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(
+            queue.fail(id, &reservation.token).unwrap(),
+            MessageState::Dead
+        );
+        assert!(matches!(
+            queue.fail(id, &reservation.token),
+            Err(Error::InvalidTransition {
+                from: MessageState::Dead,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_remove_and_requeue_check_messages() {
+        let mut queue = QoxideQueue::new();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        assert!(matches!(queue.remove(42), Err(Error::NotFound(42))));
+        assert!(matches!(
+            queue.requeue_dead_letters(&[id]),
+            Err(Error::InvalidTransition {
+                from: MessageState::Pending,
+                to: MessageState::Pending
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stale_reservation_is_rejected() {
+        let mut queue = QoxideQueue::builder()
+            .visibility_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        let stale = queue.reserve().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let current = queue
+            .reserve()
+            .expect("Expired reservation should be reclaimed");
+        assert_eq!(current.id, id);
+        assert_ne!(current.token, stale.token);
+
+        assert!(matches!(
+            queue.complete(id, &stale.token),
+            Err(Error::StaleReservation(_))
+        ));
+        assert!(matches!(
+            queue.fail(id, &stale.token),
+            Err(Error::StaleReservation(_))
+        ));
+        assert_eq!(queue.size().unwrap().reserved, 1);
+
+        queue.complete(id, &current.token).unwrap();
+    }
+
+    #[test]
+    fn test_get_message() {
+        let mut queue = QoxideQueue::builder().name("emails").build().unwrap();
+        let before = SystemTime::now() - Duration::from_millis(1);
+        let id = queue.add_with_priority(b"test".to_vec(), 3).unwrap();
+
+        let message = queue.get_message(id).unwrap();
+        assert_eq!(message.queue, "emails");
+        assert_eq!(message.state, MessageState::Pending);
+        assert_eq!(message.payload, b"test".to_vec());
+        assert_eq!(message.priority, 3);
+        assert!(message.created_at >= before);
+        assert_eq!(message.reserved_at, None);
+
+        let reservation = queue.reserve().unwrap();
+        queue.fail(id, &reservation.token).unwrap();
+        let reservation = queue.reserve().unwrap();
+        queue.complete(id, &reservation.token).unwrap();
+
+        let message = queue.get_message(id).unwrap();
+        assert_eq!(message.state, MessageState::Completed);
+        assert_eq!(message.attempt_count, 1);
+        assert!(message.reserved_at.unwrap() >= message.created_at);
+        assert!(message.completed_at.unwrap() >= message.reserved_at.unwrap());
+        assert!(matches!(queue.get_message(42), Err(Error::NotFound(42))));
+    }
+
+    #[test]
+    fn test_list_pages_with_filters() {
+        let mut queue = QoxideQueue::new();
+        let ids = queue.add_batch((0..5).map(|i| vec![i]).collect()).unwrap();
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(reservation.id, ids[0]);
+
+        let filter = ListFilter::new().state(MessageState::Pending).limit(2);
+        let first = queue.list(&filter).unwrap();
+        let second = queue.list(&filter.clone().after(first[1].id)).unwrap();
+        let third = queue.list(&filter.clone().after(second[1].id)).unwrap();
+        let listed: Vec<i64> = first
+            .iter()
+            .chain(&second)
+            .chain(&third)
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(listed, ids[1..].to_vec());
+        assert!(third.is_empty());
+
+        let reserved = queue
+            .list(&ListFilter::new().state(MessageState::Reserved))
+            .unwrap();
+        assert_eq!(reserved.len(), 1);
+        assert_eq!(reserved[0].id, ids[0]);
+
+        let range = queue
+            .list(&ListFilter::new().min_id(ids[1]).max_id(ids[2]))
+            .unwrap();
+        assert_eq!(range.len(), 2);
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        assert!(
+            queue
+                .list(&ListFilter::new().created_after(future))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_purge() {
+        let mut queue = QoxideQueue::builder().max_attempts(1).build().unwrap();
+        let ids = queue.add_batch(vec![b"a".to_vec(), b"b".to_vec()]).unwrap();
+        let reservation = queue.reserve().unwrap();
+        queue.complete(reservation.id, &reservation.token).unwrap();
+
+        assert_eq!(
+            queue
+                .purge(MessageState::Completed, Duration::from_secs(3600))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            queue
+                .purge(MessageState::Completed, Duration::ZERO)
+                .unwrap(),
+            1
+        );
+        assert!(matches!(queue.get(ids[0]), Err(Error::NotFound(_))));
+        assert_eq!(queue.size().unwrap().pending, 1);
+    }
+
+    #[test]
+    fn test_purge_messages_completed_by_older_versions() {
+        let db = TempDb::new();
+        Connection::open(&db.0)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE messages (
+                     id INTEGER PRIMARY KEY AUTOINCREMENT,
+                     state TEXT NOT NULL,
+                     payload_id INTEGER NOT NULL,
+                     attempt_count INTEGER NOT NULL DEFAULT 0
+                 );
+                 CREATE TABLE payloads (id INTEGER PRIMARY KEY AUTOINCREMENT, data BLOB NOT NULL);
+                 INSERT INTO payloads (data) VALUES (x'00');
+                 INSERT INTO messages (state, payload_id) VALUES ('COMPLETED', 1);",
+            )
+            .unwrap();
+
+        let mut queue = QoxideQueue::builder().path(&db.0).build().unwrap();
+        assert_eq!(
+            queue
+                .purge(MessageState::Completed, Duration::from_secs(3600))
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_retention_keep_last() {
+        let mut queue = QoxideQueue::builder()
+            .retention(RetentionPolicy::KeepLast(2))
+            .build()
+            .unwrap();
+        let ids = queue.add_batch((0..4).map(|i| vec![i]).collect()).unwrap();
+        for reservation in queue.reserve_batch(4).unwrap() {
+            queue.complete(reservation.id, &reservation.token).unwrap();
+        }
+
+        let completed: Vec<i64> = queue
+            .list(&ListFilter::new().state(MessageState::Completed))
+            .unwrap()
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(completed, ids[2..].to_vec());
+    }
+
+    #[test]
+    fn test_retention_max_age() {
+        let mut queue = QoxideQueue::builder()
+            .retention(RetentionPolicy::MaxAge(Duration::from_millis(10)))
+            .build()
+            .unwrap();
+        queue.add(b"old".to_vec()).unwrap();
+        let reservation = queue.reserve().unwrap();
+        queue.complete(reservation.id, &reservation.token).unwrap();
+        assert_eq!(queue.size().unwrap().completed, 1);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(queue.reserve().is_err());
+        assert_eq!(queue.size().unwrap().completed, 0);
+    }
+
+    #[test]
+    fn test_remove_deletes_payload() {
+        let mut queue = QoxideQueue::new();
+        let id = queue.add(b"test".to_vec()).unwrap();
+        queue.remove(id).unwrap();
+
+        let payloads: i64 = queue
+            .db
+            .query_row("SELECT COUNT(*) FROM payloads", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(payloads, 0);
+    }
+
+    #[test]
+    fn test_compact_frees_space() {
+        let db = TempDb::new();
+        let mut queue = QoxideQueue::builder().path(&db.0).build().unwrap();
+        queue
+            .add_batch((0..20).map(|_| vec![0; 64 * 1024]).collect())
+            .unwrap();
+        queue.purge(MessageState::Pending, Duration::ZERO).unwrap();
+
+        assert!(queue.compact().unwrap() > 0);
+        assert_eq!(queue.compact().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_identical_payloads_share_storage() {
+        let mut queue = QoxideQueue::new();
+        let payload_count = |queue: &QoxideQueue| -> i64 {
+            queue
+                .db
+                .query_row("SELECT COUNT(*) FROM payloads", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let ids = queue.fan_out(b"blob".to_vec(), 3).unwrap();
+        let other = queue.add(b"blob".to_vec()).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(queue.size().unwrap().pending, 4);
+        assert_eq!(payload_count(&queue), 1);
+
+        // The payload lives until its last message is gone
+        for id in ids {
+            queue.remove(id).unwrap();
+        }
+        assert_eq!(queue.get(other).unwrap(), b"blob".to_vec());
+        queue.remove(other).unwrap();
+        assert_eq!(payload_count(&queue), 0);
+    }
+
+    #[test]
+    fn test_reserve_is_ordered_and_exclusive_across_connections() {
+        let db = TempDb::new();
+        let mut queue = QoxideQueue::builder().path(&db.0).build().unwrap();
+        let ids = queue
+            .add_batch((0..200).map(|_| vec![0]).collect())
+            .unwrap();
+
+        // Each consumer owns a separate connection to the same file
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let mut queue = QoxideQueue::builder().path(&db.0).build().unwrap();
+                std::thread::spawn(move || {
+                    let mut reserved = Vec::new();
+                    while let Ok(reservation) = queue.reserve() {
+                        reserved.push(reservation.id);
+                    }
+                    reserved
+                })
+            })
+            .collect();
+
+        let mut all = Vec::new();
+        for consumer in consumers {
+            let reserved = consumer.join().unwrap();
+            // Every reserve takes the oldest pending message, so each consumer sees ascending IDs
+            assert!(reserved.windows(2).all(|pair| pair[0] < pair[1]));
+            all.extend(reserved);
+        }
+        all.sort();
+        assert_eq!(all, ids);
+    }
+
+    #[test]
+    fn test_retry_policy_delays_failed_message() {
+        let mut queue = QoxideQueue::builder()
+            .retry_policy(RetryPolicy::Fixed(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(
+            queue.fail(id, &reservation.token).unwrap(),
+            MessageState::Pending
+        );
+        assert!(queue.reserve().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        let reservation = queue.reserve().expect("Retry delay should have passed");
+
+        // A per-call delay overrides the policy
+        queue
+            .fail_with_delay(id, &reservation.token, Duration::ZERO)
+            .unwrap();
+        assert_eq!(queue.reserve().unwrap().id, id);
+    }
+
+    #[test]
+    fn test_fail_with_reason_and_delay() {
+        let mut queue = QoxideQueue::new();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        let options = FailOptions::new()
+            .reason("rate limited")
+            .delay(Duration::from_secs(3600));
+        queue.fail_with(id, &reservation.token, options).unwrap();
+
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        let message = queue.get_message(id).unwrap();
+        assert_eq!(message.history[0].error.as_deref(), Some("rate limited"));
+    }
+
+    #[test]
+    fn test_retry_policy_delays_reclaimed_message() {
+        let mut queue = QoxideQueue::builder()
+            .visibility_timeout(Duration::from_millis(10))
+            .retry_policy(RetryPolicy::Fixed(Duration::from_secs(3600)))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        queue.reserve().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+
+        let message = queue.get_message(id).unwrap();
+        assert_eq!(message.state, MessageState::Pending);
+        assert!(message.available_at > SystemTime::now() + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_retry_policy_delays() {
+        let second = Duration::from_secs(1);
+        assert_eq!(RetryPolicy::Fixed(second).delay(3), second);
+        assert_eq!(RetryPolicy::Linear(second).delay(3), second * 3);
+
+        let policy = RetryPolicy::Exponential {
+            base: second,
+            max: second * 10,
+        };
+        for (attempt, wait) in [(1, second), (3, second * 4), (10, second * 10)] {
+            let delay = policy.delay(attempt);
+            assert!(delay >= wait / 2 && delay <= wait, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn test_attempt_history() {
+        let mut queue = QoxideQueue::builder()
+            .max_attempts(3)
+            .visibility_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        queue
+            .fail_with_reason(id, &reservation.token, "connection refused")
+            .unwrap();
+        queue.reserve().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let reservation = queue
+            .reserve()
+            .expect("Expired reservation should be reclaimed");
+        assert_eq!(queue.get_message(id).unwrap().history[2].outcome, None);
+        queue
+            .fail_with_reason(id, &reservation.token, "out of memory")
+            .unwrap();
+
+        let message = queue.get_message(id).unwrap();
+        assert_eq!(message.state, MessageState::Dead);
+        let outcomes: Vec<_> = message
+            .history
+            .iter()
+            .map(|attempt| (attempt.number, attempt.outcome, attempt.error.as_deref()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, Some(AttemptOutcome::Failed), Some("connection refused")),
+                (2, Some(AttemptOutcome::TimedOut), None),
+                (3, Some(AttemptOutcome::Failed), Some("out of memory")),
+            ]
+        );
+        assert!(
+            message
+                .history
+                .iter()
+                .all(|attempt| attempt.ended_at >= Some(attempt.started_at))
+        );
+    }
+
+    #[test]
+    fn test_worker_completes_and_fails_messages() {
+        let queue = QoxideQueue::builder()
+            .max_attempts(1)
+            .build_shared()
+            .unwrap();
+        let ids = queue
+            .add_batch(vec![b"ok".to_vec(), b"error".to_vec(), b"panic".to_vec()])
+            .unwrap();
+
+        let worker = Worker::new(queue.clone())
+            .concurrency(2)
+            .start(|job: &Reservation| match job.payload.as_slice() {
+                b"error" => Err("bad input"),
+                b"panic" => panic!("boom"),
+                _ => Ok(()),
+            });
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while queue
+            .size()
+            .map(|size| size.pending + size.reserved)
+            .unwrap()
+            > 0
+        {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "Worker should drain the queue"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        worker.shutdown();
+
+        assert_eq!(
+            queue.get_message(ids[0]).unwrap().state,
+            MessageState::Completed
+        );
+        let errors: Vec<_> = ids[1..]
+            .iter()
+            .map(|&id| queue.get_message(id).unwrap().history[0].error.clone())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                Some("bad input".to_string()),
+                Some("handler panicked: boom".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_worker_shutdown_finishes_in_flight_jobs() {
+        let queue = QoxideQueue::builder().build_shared().unwrap();
+        let id = queue.add(b"slow".to_vec()).unwrap();
+
+        let worker = Worker::new(queue.clone()).start(|_: &Reservation| {
+            std::thread::sleep(Duration::from_millis(100));
+            Ok::<(), String>(())
+        });
+        while queue.size().unwrap().reserved == 0 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        worker.shutdown();
+
+        assert_eq!(
+            queue.get_message(id).unwrap().state,
+            MessageState::Completed
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_keeps_reservation() {
+        let mut queue = QoxideQueue::builder()
+            .visibility_timeout(Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let id = queue.add(b"test".to_vec()).unwrap();
+        let reservation = queue.reserve().unwrap();
+
+        // A lapsed lease is renewed for a full visibility timeout by a heartbeat
+        queue
+            .extend(id, &reservation.token, Duration::ZERO)
+            .unwrap();
+        queue.heartbeat(id, &reservation.token).unwrap();
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        let reserved_until = queue.get_message(id).unwrap().reserved_until.unwrap();
+        assert!(reserved_until > SystemTime::now() + Duration::from_secs(3000));
+
+        // Shortening the lease lets the next reserve reclaim the message
+        queue
+            .extend(id, &reservation.token, Duration::ZERO)
+            .unwrap();
+        let reclaimed = queue.reserve().unwrap();
+        assert!(matches!(
+            queue.heartbeat(id, &reservation.token),
+            Err(Error::StaleReservation(_))
+        ));
+
+        queue.complete(id, &reclaimed.token).unwrap();
+        assert!(matches!(
+            queue.extend(id, &reclaimed.token, Duration::from_secs(1)),
+            Err(Error::InvalidTransition {
+                from: MessageState::Completed,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_heartbeat_without_visibility_timeout_keeps_extension() {
+        let mut queue = QoxideQueue::new();
+        let id = queue.add(b"test".to_vec()).unwrap();
+        let reservation = queue.reserve().unwrap();
+
+        queue
+            .extend(id, &reservation.token, Duration::from_secs(3600))
+            .unwrap();
+        queue.heartbeat(id, &reservation.token).unwrap();
+        assert!(queue.get_message(id).unwrap().reserved_until.is_some());
+        assert!(matches!(
+            queue.heartbeat(id, "stale"),
+            Err(Error::StaleReservation(_))
+        ));
+    }
+
+    #[test]
+    fn test_dependencies_reserve_in_order() {
+        let mut queue = QoxideQueue::new();
+        let a = queue.add(b"a".to_vec()).unwrap();
+        let b = queue.add_after(b"b".to_vec(), &[a]).unwrap();
+        let c = queue.add_after(b"c".to_vec(), &[a, b]).unwrap();
+        assert!(matches!(
+            queue.add_after(b"d".to_vec(), &[999]),
+            Err(Error::NotFound(999))
+        ));
+
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(reservation.id, a);
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        queue.complete(a, &reservation.token).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(reservation.id, b);
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        queue.complete(b, &reservation.token).unwrap();
+
+        assert_eq!(queue.reserve().unwrap().id, c);
+    }
+
+    #[test]
+    fn test_dead_parent_policy() {
+        let mut queue = QoxideQueue::builder()
+            .max_attempts(1)
+            .dead_parent_policy(DeadParentPolicy::CancelChildren)
+            .build()
+            .unwrap();
+        let a = queue.add(b"a".to_vec()).unwrap();
+        let b = queue.add_after(b"b".to_vec(), &[a]).unwrap();
+        let c = queue.add_after(b"c".to_vec(), &[b]).unwrap();
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(
+            queue.fail(a, &reservation.token).unwrap(),
+            MessageState::Dead
+        );
+        assert_eq!(queue.get_message(b).unwrap().state, MessageState::Cancelled);
+        assert_eq!(queue.get_message(c).unwrap().state, MessageState::Cancelled);
+        assert_eq!(queue.size().unwrap().cancelled, 2);
+
+        let mut queue = QoxideQueue::builder().max_attempts(1).build().unwrap();
+        let a = queue.add(b"a".to_vec()).unwrap();
+        let b = queue.add_after(b"b".to_vec(), &[a]).unwrap();
+        let reservation = queue.reserve().unwrap();
+        queue.fail(a, &reservation.token).unwrap();
+        assert_eq!(queue.get_message(b).unwrap().state, MessageState::Pending);
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+
+        queue.requeue_dead_letters(&[a]).unwrap();
+        let reservation = queue.reserve().unwrap();
+        assert_eq!(reservation.id, a);
+        queue.complete(a, &reservation.token).unwrap();
+        assert_eq!(queue.reserve().unwrap().id, b);
+    }
+
+    #[test]
+    fn test_dropped_expired_parent_cancels_children() {
+        let mut queue = QoxideQueue::builder()
+            .drop_expired(true)
+            .dead_parent_policy(DeadParentPolicy::CancelChildren)
+            .build()
+            .unwrap();
+        let parent = queue
+            .add_with_ttl(b"parent".to_vec(), Duration::from_millis(10))
+            .unwrap();
+        let child = queue.add_after(b"child".to_vec(), &[parent]).unwrap();
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        assert!(matches!(queue.get_message(parent), Err(Error::NotFound(_))));
+        assert_eq!(
+            queue.get_message(child).unwrap().state,
+            MessageState::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_only_remove_unblocks_children() {
+        let mut queue = QoxideQueue::builder().max_attempts(1).build().unwrap();
+        let purged = queue.add(b"purged".to_vec()).unwrap();
+        let removed = queue.add(b"removed".to_vec()).unwrap();
+        let first = queue.add_after(b"first".to_vec(), &[purged]).unwrap();
+        let second = queue.add_after(b"second".to_vec(), &[removed]).unwrap();
+        let reservation = queue.reserve().unwrap();
+        queue.fail(purged, &reservation.token).unwrap();
+        queue.purge(MessageState::Dead, Duration::ZERO).unwrap();
+
+        let reservation = queue.reserve().unwrap();
+        queue.fail(removed, &reservation.token).unwrap();
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+        assert_eq!(
+            queue.get_message(first).unwrap().state,
+            MessageState::Pending
+        );
+
+        queue.remove(removed).unwrap();
+        assert_eq!(queue.reserve().unwrap().id, second);
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+    }
+
+    #[test]
+    fn test_group_adds_callback_when_finished() {
+        let db = TempDb::new();
+        let mut queue = QoxideQueue::builder()
+            .path(&db.0)
+            .max_attempts(1)
+            .build()
+            .unwrap();
+        let mut reduce = QoxideQueue::builder()
+            .path(&db.0)
+            .name("reduce")
+            .build()
+            .unwrap();
+        let group = queue
+            .add_group(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], "reduce")
+            .unwrap();
+        assert_eq!(group.messages.len(), 3);
+        assert!(matches!(
+            queue.add_group(vec![b"d".to_vec()], ""),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            queue.group(group.id + 1),
+            Err(Error::GroupNotFound(_))
+        ));
+
+        for _ in 0..2 {
+            let reservation = queue.reserve().unwrap();
+            queue.complete(reservation.id, &reservation.token).unwrap();
+        }
+        let summary = queue.group(group.id).unwrap();
+        assert_eq!((summary.succeeded, summary.remaining), (2, 1));
+        assert_eq!(summary.callback_id, None);
+
+        let reservation = queue.reserve().unwrap();
+        queue.fail(reservation.id, &reservation.token).unwrap();
+        let summary = queue.group(group.id).unwrap();
+        assert_eq!(
+            (
+                summary.total,
+                summary.succeeded,
+                summary.died,
+                summary.remaining
+            ),
+            (3, 2, 1, 0)
+        );
+        let callback_id = summary
+            .callback_id
+            .expect("Finished group should have a callback");
+        assert!(matches!(queue.reserve(), Err(Error::Empty)));
+
+        let reservation = reduce.reserve().unwrap();
+        assert_eq!(reservation.id, callback_id);
+        assert_eq!(
+            reservation.payload,
+            format!(r#"{{"group":{},"succeeded":2,"died":1}}"#, group.id).into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_expired_group_finishes_on_read() {
+        let mut queue = QoxideQueue::builder()
+            .ttl(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let group = queue.add_group(vec![b"a".to_vec()], "reduce").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(queue.size().unwrap().expired, 1);
+        let summary = queue.group(group.id).unwrap();
+        assert_eq!((summary.died, summary.remaining), (1, 0));
+        assert!(summary.callback_id.is_some());
+    }
 }
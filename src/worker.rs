@@ -0,0 +1,198 @@
+use crate::{Error, Reservation, SharedQoxideQueue};
+use std::any::Any;
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const DEFAULT_CONCURRENCY: usize = 1;
+
+/// How long an idle worker thread waits for a message before checking for shutdown.
+const IDLE_WAIT: Duration = Duration::from_millis(200);
+
+/// How long a worker thread backs off after the queue returns an error.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How often a backing-off worker thread checks for shutdown.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs a handler over every message in a queue on a pool of threads.
+///
+/// Each thread reserves a message, passes it to the handler, and completes the
+/// message if the handler returns `Ok`. If the handler returns `Err` or panics,
+/// the message is failed with the error or panic message as the reason, so the
+/// queue's max attempts and retry policy apply as usual. If completing or failing
+/// hits a storage error, the thread keeps retrying until it succeeds or the worker
+/// shuts down.
+///
+/// # Example
+///
+/// ```
+/// use qoxide::{QoxideQueue, Reservation, Worker};
+///
+/// # fn main() -> Result<(), qoxide::Error> {
+/// let queue = QoxideQueue::builder().build_shared()?;
+/// queue.add(b"job".to_vec())?;
+///
+/// let worker = Worker::new(queue).concurrency(4).start(|job: &Reservation| {
+///     if job.payload.is_empty() {
+///         return Err("empty payload");
+///     }
+///     // Process the job...
+///     Ok(())
+/// });
+///
+/// // Stops reserving, then waits for in-flight jobs to finish
+/// worker.shutdown();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Worker {
+    queue: SharedQoxideQueue,
+    concurrency: usize,
+}
+
+impl Worker {
+    /// Creates a worker for `queue` that processes one message at a time.
+    pub fn new(queue: SharedQoxideQueue) -> Self {
+        Self {
+            queue,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Sets how many messages are processed at once, each on its own thread.
+    ///
+    /// Values below 1 are treated as 1. Threads share the queue's connection pool,
+    /// so use a [`pool_size`](crate::QoxideQueueBuilder::pool_size) of at least the
+    /// concurrency to avoid threads waiting on each other for connections.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Starts the worker threads, returning a handle to shut them down.
+    pub fn start<F, E>(self, handler: F) -> WorkerHandle
+    where
+        F: Fn(&Reservation) -> Result<(), E> + Send + Sync + 'static,
+        E: Display,
+    {
+        let stopping = Arc::new(AtomicBool::new(false));
+        let handler = Arc::new(handler);
+        let threads = (0..self.concurrency)
+            .map(|_| {
+                let queue = self.queue.clone();
+                let stopping = Arc::clone(&stopping);
+                let handler = Arc::clone(&handler);
+                thread::spawn(move || run(&queue, &stopping, &*handler))
+            })
+            .collect();
+        WorkerHandle { stopping, threads }
+    }
+}
+
+/// A running [`Worker`].
+///
+/// Dropping the handle shuts the worker down the same way as [`shutdown`](Self::shutdown).
+pub struct WorkerHandle {
+    stopping: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl WorkerHandle {
+    /// Stops reserving new messages and blocks until every in-flight message has been
+    /// handled and completed or failed.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            // Handler panics are caught in the thread, so joining cannot fail
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run<F, E>(queue: &SharedQoxideQueue, stopping: &AtomicBool, handler: &F)
+where
+    F: Fn(&Reservation) -> Result<(), E>,
+    E: Display,
+{
+    while !stopping.load(Ordering::SeqCst) {
+        let reservation = match queue.reserve_wait(IDLE_WAIT) {
+            Ok(reservation) => reservation,
+            Err(Error::Empty) => continue,
+            Err(_) => {
+                back_off(stopping);
+                continue;
+            }
+        };
+
+        let reason = match panic::catch_unwind(AssertUnwindSafe(|| handler(&reservation))) {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(panic) => Some(format!("handler panicked: {}", panic_message(&*panic))),
+        };
+        finish(queue, stopping, &reservation, reason.as_deref());
+    }
+}
+
+/// Completes the message, or fails it with `reason`, retrying storage errors until
+/// it succeeds or the worker is shutting down.
+fn finish(
+    queue: &SharedQoxideQueue,
+    stopping: &AtomicBool,
+    reservation: &Reservation,
+    reason: Option<&str>,
+) {
+    loop {
+        let result = match reason {
+            None => queue.complete(reservation.id, &reservation.token),
+            Some(reason) => queue
+                .fail_with_reason(reservation.id, &reservation.token, reason)
+                .map(|_| ()),
+        };
+        match result {
+            Ok(()) => return,
+            // The reservation was reclaimed, or the message removed, while the handler ran
+            // and is no longer ours to finish
+            Err(
+                Error::StaleReservation(_) | Error::InvalidTransition { .. } | Error::NotFound(_),
+            ) => {
+                return;
+            }
+            // Left reserved, the message is reclaimed once its visibility timeout passes
+            Err(_) if stopping.load(Ordering::SeqCst) => return,
+            Err(_) => back_off(stopping),
+        }
+    }
+}
+
+/// Sleeps for [`ERROR_BACKOFF`], returning early if the worker starts shutting down.
+fn back_off(stopping: &AtomicBool) {
+    let mut slept = Duration::ZERO;
+    while slept < ERROR_BACKOFF && !stopping.load(Ordering::SeqCst) {
+        thread::sleep(STOP_CHECK_INTERVAL);
+        slept += STOP_CHECK_INTERVAL;
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}